@@ -0,0 +1,397 @@
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use prost::encoding::encoded_len_varint;
+use prost::Message;
+use std::str::FromStr;
+
+use crate::wire::{AnnouncementBatch, FileAnnouncement, FileSignature, SignedAnnouncement};
+use crate::NodeError;
+
+/// Largest encoded [`AnnouncementBatch`], below gossipsub's default maximum
+/// transmit size of 64 KiB to leave room for the gossip envelope.
+pub const MAX_BATCH_LEN: usize = 60 * 1024;
+
+/// Most tags a file may be announced with.
+pub const MAX_TAGS: usize = 8;
+
+/// Longest tag, in bytes.
+const MAX_TAG_LEN: usize = 32;
+
+/// Upper bound of the encoded `page` and `page_count` fields.
+const PAGE_FIELDS_LEN: usize = 2 * (1 + 5);
+
+/// Prefix of the payload an announcement signature covers, so that it
+/// cannot be passed off as a signature over another kind of message.
+const SIGNING_DOMAIN: &[u8] = b"workshop-announcement:";
+
+/// Prefix of the payload a [`FileSignature`] covers.
+const FILE_SIGNING_DOMAIN: &[u8] = b"workshop-file:";
+
+/// Splits `files` into as few batches as possible, each encoding to at most
+/// [`MAX_BATCH_LEN`] bytes. The files keep their order across batches.
+pub fn batches(files: &[FileAnnouncement]) -> Result<Vec<AnnouncementBatch>, NodeError> {
+    let mut batches = Vec::new();
+    let mut current = Vec::new();
+    let mut current_len = PAGE_FIELDS_LEN;
+    for file in files {
+        let len = file.encoded_len();
+        let entry_len = 1 + encoded_len_varint(len as u64) + len;
+        if PAGE_FIELDS_LEN + entry_len > MAX_BATCH_LEN {
+            return Err(NodeError::AnnouncementTooLarge {
+                name: file.name.clone(),
+            });
+        }
+        if current_len + entry_len > MAX_BATCH_LEN {
+            batches.push(std::mem::take(&mut current));
+            current_len = PAGE_FIELDS_LEN;
+        }
+        current.push(file.clone());
+        current_len += entry_len;
+    }
+    if !current.is_empty() {
+        batches.push(current);
+    }
+
+    let page_count = batches.len() as u32;
+    Ok(batches
+        .into_iter()
+        .enumerate()
+        .map(|(page, files)| AnnouncementBatch {
+            files,
+            page: page as u32,
+            page_count,
+        })
+        .collect())
+}
+
+/// Signs `announcement` as its provider `keypair`, valid until `expires_at`
+/// seconds since the Unix epoch.
+pub fn sign(
+    keypair: &Keypair,
+    announcement: FileAnnouncement,
+    expires_at: u64,
+) -> Result<SignedAnnouncement, NodeError> {
+    let mut signed = SignedAnnouncement {
+        announcement: Some(announcement),
+        provider_key: keypair.public().to_protobuf_encoding(),
+        expires_at,
+        signature: Vec::new(),
+    };
+    signed.signature = keypair.sign(&signing_payload(&signed))?;
+    Ok(signed)
+}
+
+/// Checks that `signed` carries a valid signature by its provider and has
+/// not expired at `now`, in seconds since the Unix epoch. Returns the
+/// provider and the announcement.
+///
+/// This holds however the announcement was received, e.g. forwarded by
+/// another peer or imported from a catalog export.
+pub fn verify(
+    signed: &SignedAnnouncement,
+    now: u64,
+) -> Result<(PeerId, &FileAnnouncement), NodeError> {
+    let announcement = signed
+        .announcement
+        .as_ref()
+        .ok_or_else(|| NodeError::Protocol("signed announcement without content".to_string()))?;
+    let provider = PublicKey::from_protobuf_encoding(&signed.provider_key)
+        .map_err(|e| NodeError::Protocol(format!("invalid provider key: {e}")))?;
+    if !provider.verify(&signing_payload(signed), &signed.signature) {
+        return Err(NodeError::InvalidSignature {
+            name: announcement.name.clone(),
+        });
+    }
+    if now > signed.expires_at {
+        return Err(NodeError::AnnouncementExpired {
+            name: announcement.name.clone(),
+        });
+    }
+    Ok((provider.to_peer_id(), announcement))
+}
+
+fn signing_payload(signed: &SignedAnnouncement) -> Vec<u8> {
+    let unsigned = SignedAnnouncement {
+        signature: Vec::new(),
+        ..signed.clone()
+    };
+    let mut payload = SIGNING_DOMAIN.to_vec();
+    unsigned
+        .encode(&mut payload)
+        .expect("a Vec grows as needed");
+    payload
+}
+
+/// Signs the file with content hash `sha256` as `keypair`, for announcing
+/// it as a release of the signer.
+pub fn sign_file(keypair: &Keypair, sha256: &[u8; 32]) -> Result<FileSignature, NodeError> {
+    Ok(FileSignature {
+        signer_key: keypair.public().to_protobuf_encoding(),
+        signature: keypair.sign(&[FILE_SIGNING_DOMAIN, sha256].concat())?,
+    })
+}
+
+/// Returns who signed the file `announcement` offers, `None` if it is
+/// unsigned.
+///
+/// The signature covers the announced hash, so a download that matches the
+/// hash is content the signer vouched for, whichever peer provided it.
+pub fn signer(announcement: &FileAnnouncement) -> Result<Option<PeerId>, NodeError> {
+    let Some(signature) = &announcement.signature else {
+        return Ok(None);
+    };
+    let signer = PublicKey::from_protobuf_encoding(&signature.signer_key)
+        .map_err(|e| NodeError::Protocol(format!("invalid signer key: {e}")))?;
+    let payload = [FILE_SIGNING_DOMAIN, &announcement.sha256].concat();
+    if !signer.verify(&payload, &signature.signature) {
+        return Err(NodeError::InvalidSignature {
+            name: announcement.name.clone(),
+        });
+    }
+    Ok(Some(signer.to_peer_id()))
+}
+
+/// Parses a comma-separated tag list such as `slides,day1`. Tags are
+/// lowercased, sorted and deduplicated, and may only contain ASCII letters,
+/// digits, `-` and `_`.
+pub fn parse_tags(list: &str) -> Result<Vec<String>, NodeError> {
+    let mut tags = Vec::new();
+    for tag in list.split(',') {
+        let tag = tag.trim().to_ascii_lowercase();
+        let valid = tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if tag.is_empty() || tag.len() > MAX_TAG_LEN || !valid {
+            return Err(NodeError::Command(format!(
+                "invalid tag {tag:?}, expected up to {MAX_TAG_LEN} letters, digits, - or _"
+            )));
+        }
+        tags.push(tag);
+    }
+    tags.sort_unstable();
+    tags.dedup();
+    if tags.len() > MAX_TAGS {
+        return Err(NodeError::Command(format!(
+            "too many tags, at most {MAX_TAGS} are allowed"
+        )));
+    }
+    Ok(tags)
+}
+
+/// `PUT <path> [--tags <tag>,...]`, offering a file for download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PutCommand {
+    pub path: String,
+    pub tags: Vec<String>,
+}
+
+impl FromStr for PutCommand {
+    type Err = NodeError;
+
+    fn from_str(line: &str) -> Result<Self, NodeError> {
+        let invalid = || NodeError::Command(line.to_string());
+        let line = line.trim();
+        let (command, args) = line.split_once(' ').ok_or_else(invalid)?;
+        if !command.eq_ignore_ascii_case("PUT") {
+            return Err(invalid());
+        }
+        let (path, tags) = match args.rsplit_once(" --tags ") {
+            Some((path, tags)) => (path, parse_tags(tags)?),
+            None => (args, Vec::new()),
+        };
+        let path = path.trim();
+        if path.is_empty() || path.starts_with("--") {
+            return Err(invalid());
+        }
+        Ok(PutCommand {
+            path: path.to_string(),
+            tags,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(i: usize) -> FileAnnouncement {
+        FileAnnouncement {
+            name: format!("{i:0>200}.pdf"),
+            size: i as u64,
+            sha256: vec![i as u8; 32],
+            tags: vec!["day1".into()],
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn small_catalogs_fit_one_batch() {
+        let files: Vec<_> = (0..3).map(file).collect();
+        let batches = batches(&files).unwrap();
+
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].files, files);
+        assert_eq!((batches[0].page, batches[0].page_count), (0, 1));
+        assert!(super::batches(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn large_catalogs_are_paginated() {
+        let files: Vec<_> = (0..1000).map(file).collect();
+        let batches = batches(&files).unwrap();
+
+        assert!(batches.len() > 1);
+        for (i, batch) in batches.iter().enumerate() {
+            assert!(batch.encoded_len() <= MAX_BATCH_LEN);
+            assert_eq!(batch.page, i as u32);
+            assert_eq!(batch.page_count, batches.len() as u32);
+        }
+        // Only the last batch may have room for another file.
+        for batch in &batches[..batches.len() - 1] {
+            assert!(batch.encoded_len() + file(0).encoded_len() + 3 > MAX_BATCH_LEN);
+        }
+        let rejoined: Vec<_> = batches.into_iter().flat_map(|b| b.files).collect();
+        assert_eq!(rejoined, files);
+    }
+
+    #[test]
+    fn rejects_files_that_cannot_fit() {
+        let huge = FileAnnouncement {
+            name: "x".repeat(MAX_BATCH_LEN),
+            ..file(0)
+        };
+        assert!(matches!(
+            batches(&[file(1), huge]),
+            Err(NodeError::AnnouncementTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn signed_announcements_verify() {
+        let keypair = Keypair::generate_ed25519();
+        let signed = sign(&keypair, file(1), 100).unwrap();
+
+        // As if forwarded by another peer.
+        let forwarded = SignedAnnouncement::decode(&signed.encode_to_vec()[..]).unwrap();
+        let (provider, announcement) = verify(&forwarded, 100).unwrap();
+        assert_eq!(provider, keypair.public().to_peer_id());
+        assert_eq!(announcement, &file(1));
+
+        assert!(matches!(
+            verify(&signed, 101),
+            Err(NodeError::AnnouncementExpired { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_altered_announcements() {
+        let keypair = Keypair::generate_ed25519();
+        let signed = sign(&keypair, file(1), 100).unwrap();
+        let other_key = Keypair::generate_ed25519().public().to_protobuf_encoding();
+
+        for altered in [
+            SignedAnnouncement {
+                announcement: Some(file(2)),
+                ..signed.clone()
+            },
+            SignedAnnouncement {
+                expires_at: 200,
+                ..signed.clone()
+            },
+            SignedAnnouncement {
+                provider_key: other_key,
+                ..signed.clone()
+            },
+            SignedAnnouncement {
+                signature: vec![0; 64],
+                ..signed.clone()
+            },
+        ] {
+            assert!(
+                matches!(verify(&altered, 0), Err(NodeError::InvalidSignature { .. })),
+                "{altered:?}"
+            );
+        }
+        for malformed in [
+            SignedAnnouncement {
+                announcement: None,
+                ..signed.clone()
+            },
+            SignedAnnouncement {
+                provider_key: vec![1, 2, 3],
+                ..signed
+            },
+        ] {
+            assert!(matches!(verify(&malformed, 0), Err(NodeError::Protocol(_))));
+        }
+    }
+
+    #[test]
+    fn file_signatures_name_the_signer() {
+        let leader = Keypair::generate_ed25519();
+        let mut release = file(1);
+        assert_eq!(signer(&release).unwrap(), None);
+
+        release.signature = Some(sign_file(&leader, &[1; 32]).unwrap());
+        assert_eq!(
+            signer(&release).unwrap(),
+            Some(leader.public().to_peer_id())
+        );
+        // Announced by anyone else, under any name.
+        let reannounced = FileAnnouncement {
+            name: "copy.pdf".into(),
+            ..release.clone()
+        };
+        assert_eq!(
+            signer(&reannounced).unwrap(),
+            Some(leader.public().to_peer_id())
+        );
+
+        let other_content = FileAnnouncement {
+            sha256: vec![2; 32],
+            ..release.clone()
+        };
+        assert!(matches!(
+            signer(&other_content),
+            Err(NodeError::InvalidSignature { .. })
+        ));
+        release.signature.as_mut().unwrap().signer_key = vec![1, 2, 3];
+        assert!(matches!(signer(&release), Err(NodeError::Protocol(_))));
+    }
+
+    #[test]
+    fn parses_tags() {
+        assert_eq!(
+            parse_tags(" Slides,day1 ,slides").unwrap(),
+            ["day1", "slides"]
+        );
+        for invalid in ["", "a,,b", "two words", "caf\u{e9}", &"x".repeat(33)] {
+            assert!(parse_tags(invalid).is_err(), "{invalid:?}");
+        }
+        let many: Vec<_> = (0..=MAX_TAGS).map(|i| i.to_string()).collect();
+        assert!(parse_tags(&many.join(",")).is_err());
+    }
+
+    #[test]
+    fn parses_put_commands() {
+        assert_eq!(
+            "PUT notes.pdf --tags slides,day1"
+                .parse::<PutCommand>()
+                .unwrap(),
+            PutCommand {
+                path: "notes.pdf".into(),
+                tags: vec!["day1".into(), "slides".into()],
+            }
+        );
+        assert_eq!(
+            "put my notes.pdf".parse::<PutCommand>().unwrap(),
+            PutCommand {
+                path: "my notes.pdf".into(),
+                tags: vec![],
+            }
+        );
+        for invalid in ["PUT", "PUT --tags a", "GET notes.pdf", "PUT a --tags b c"] {
+            assert!(invalid.parse::<PutCommand>().is_err(), "{invalid:?}");
+        }
+    }
+}
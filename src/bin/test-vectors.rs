@@ -0,0 +1,72 @@
+use clap::{Parser, Subcommand};
+use libp2p_workshop_node::test_vectors::{self, vectors};
+use libp2p_workshop_node::NodeError;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Emits and verifies canonical encodings of the node's wire messages.
+#[derive(Debug, Parser)]
+#[clap(name = "test-vectors")]
+struct Opts {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Write every test vector to `<dir>/<name>.bin`.
+    Emit { dir: PathBuf },
+    /// Check files named `<name>.bin` against the test vector of that name.
+    Verify {
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+    },
+}
+
+fn main() -> ExitCode {
+    match Opts::parse().command {
+        Command::Emit { dir } => match emit(&dir) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                ExitCode::FAILURE
+            }
+        },
+        Command::Verify { files } => {
+            let mut failed = false;
+            for file in files {
+                match verify(&file) {
+                    Ok(()) => println!("ok    {}", file.display()),
+                    Err(e) => {
+                        println!("FAIL  {}: {e}", file.display());
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            }
+        }
+    }
+}
+
+fn emit(dir: &PathBuf) -> Result<(), NodeError> {
+    std::fs::create_dir_all(dir)?;
+    for vector in vectors() {
+        std::fs::write(
+            dir.join(format!("{}.bin", vector.name)),
+            vector.sample.encode(),
+        )?;
+    }
+    Ok(())
+}
+
+fn verify(file: &PathBuf) -> Result<(), NodeError> {
+    let name = file
+        .file_stem()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    test_vectors::find(&name)?.verify(&std::fs::read(file)?)
+}
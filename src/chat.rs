@@ -0,0 +1,353 @@
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::chat_log::MessageId;
+use crate::wire::chat_envelope::{Action, Event, Join, Leave, Notice, PayloadType};
+use crate::wire::ChatEnvelope;
+use crate::NodeError;
+
+/// Prefix of a typed line that is sent as an action, as in `/me waves`.
+const ACTION_PREFIX: &str = "/me ";
+
+/// Most characters of a message quoted above a reply to it.
+const QUOTE_LEN: usize = 60;
+
+/// Number of mentions `MENTIONS` lists unless told otherwise.
+const DEFAULT_MENTIONS: usize = 20;
+
+/// Turns a line typed by the user into a message for `topic`: `/me <text>`
+/// becomes an action, anything else plain text.
+pub fn compose(topic: &str, line: &str, sent_at: u64) -> ChatEnvelope {
+    match line.strip_prefix(ACTION_PREFIX) {
+        Some(action) => message(
+            topic,
+            action.trim_start(),
+            sent_at,
+            Some(Event::Action(Action {})),
+        ),
+        None => message(topic, line, sent_at, None),
+    }
+}
+
+/// A message for `topic` carrying `data` that need not be text, e.g. a
+/// small file pasted into the chat.
+pub fn binary(topic: &str, data: Vec<u8>, sent_at: u64) -> ChatEnvelope {
+    ChatEnvelope {
+        payload_type: PayloadType::Binary.into(),
+        data,
+        ..message(topic, "", sent_at, None)
+    }
+}
+
+/// Like [`compose`], for a line replying to the message `parent`.
+pub fn reply(topic: &str, parent: MessageId, line: &str, sent_at: u64) -> ChatEnvelope {
+    ChatEnvelope {
+        parent_id: Some(parent.into()),
+        ..compose(topic, line, sent_at)
+    }
+}
+
+/// Fills in the peers `message` mentions as `@nickname`, resolving
+/// nicknames with `nicknames`. Unknown nicknames are left unresolved.
+pub fn mention(message: ChatEnvelope, nicknames: &HashMap<String, PeerId>) -> ChatEnvelope {
+    let mut mentions = Vec::new();
+    for nickname in mentioned_nicknames(&message.body) {
+        if let Some(peer_id) = nicknames.get(nickname) {
+            let peer_id = peer_id.to_bytes();
+            if !mentions.contains(&peer_id) {
+                mentions.push(peer_id);
+            }
+        }
+    }
+    ChatEnvelope {
+        mentions,
+        ..message
+    }
+}
+
+/// Returns the nicknames `body` mentions, in order: words starting with `@`
+/// followed by letters, digits, `-` or `_`.
+pub fn mentioned_nicknames(body: &str) -> Vec<&str> {
+    body.split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|word| {
+            let end = word
+                .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+                .unwrap_or(word.len());
+            &word[..end]
+        })
+        .filter(|nickname| !nickname.is_empty())
+        .collect()
+}
+
+/// Whether `message` mentions `peer_id`, e.g. to highlight it for that peer.
+pub fn mentions(message: &ChatEnvelope, peer_id: &PeerId) -> bool {
+    let peer_id = peer_id.to_bytes();
+    message.mentions.contains(&peer_id)
+}
+
+pub fn join(topic: &str, sent_at: u64) -> ChatEnvelope {
+    message(topic, "", sent_at, Some(Event::Join(Join {})))
+}
+
+pub fn leave(topic: &str, sent_at: u64) -> ChatEnvelope {
+    message(topic, "", sent_at, Some(Event::Leave(Leave {})))
+}
+
+/// A notice from the node itself, e.g. that a download finished.
+pub fn notice(topic: &str, text: &str, sent_at: u64) -> ChatEnvelope {
+    message(topic, text, sent_at, Some(Event::Notice(Notice {})))
+}
+
+fn message(topic: &str, body: &str, sent_at: u64, event: Option<Event>) -> ChatEnvelope {
+    ChatEnvelope {
+        topic: topic.to_string(),
+        body: body.to_string(),
+        sent_at,
+        event,
+        ..ChatEnvelope::default()
+    }
+}
+
+/// Whether `message` only reports someone joining or leaving, for filters
+/// that hide such noise.
+pub fn is_presence(message: &ChatEnvelope) -> bool {
+    matches!(message.event, Some(Event::Join(_) | Event::Leave(_)))
+}
+
+/// Formats `message` by `author` for display, one line per kind of event.
+pub fn render(message: &ChatEnvelope, author: &str) -> String {
+    match message.event {
+        None if message.payload_type() == PayloadType::Binary => format!(
+            "<{author}> [binary, {} bytes] {}",
+            message.data.len(),
+            base64(&message.data)
+        ),
+        None => format!("<{author}> {}", message.body),
+        Some(Event::Action(_)) => format!("* {author} {}", message.body),
+        Some(Event::Join(_)) => format!("--> {author} joined {}", message.topic),
+        Some(Event::Leave(_)) => format!("<-- {author} left {}", message.topic),
+        Some(Event::Notice(_)) => format!("-- {}", message.body),
+    }
+}
+
+/// Encodes `data` as standard base64 with padding.
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, &b)| bits | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Formats the snippet of `parent` by `author` shown above a reply to it.
+pub fn quote(parent: &ChatEnvelope, author: &str) -> String {
+    if parent.payload_type() == PayloadType::Binary {
+        return format!("> {author}: [binary, {} bytes]", parent.data.len());
+    }
+    let mut snippet: String = parent.body.chars().take(QUOTE_LEN).collect();
+    if parent.body.chars().nth(QUOTE_LEN).is_some() {
+        snippet.push('…');
+    }
+    format!("> {author}: {snippet}")
+}
+
+/// `REPLY <msg_id> <text>`, replying to the message `<peer-id>/<seq>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplyCommand {
+    pub parent: MessageId,
+    pub text: String,
+}
+
+impl FromStr for ReplyCommand {
+    type Err = NodeError;
+
+    fn from_str(line: &str) -> Result<Self, NodeError> {
+        let invalid = || NodeError::Command(line.to_string());
+        let (command, args) = line.trim().split_once(' ').ok_or_else(invalid)?;
+        if !command.eq_ignore_ascii_case("REPLY") {
+            return Err(invalid());
+        }
+        let (parent, text) = args.trim_start().split_once(' ').ok_or_else(invalid)?;
+        let text = text.trim_start();
+        if text.is_empty() {
+            return Err(invalid());
+        }
+        Ok(ReplyCommand {
+            parent: parent.parse()?,
+            text: text.to_string(),
+        })
+    }
+}
+
+/// `MENTIONS [<count>]`, listing the latest messages mentioning the local
+/// peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MentionsCommand {
+    pub count: usize,
+}
+
+impl FromStr for MentionsCommand {
+    type Err = NodeError;
+
+    fn from_str(line: &str) -> Result<Self, NodeError> {
+        let invalid = || NodeError::Command(line.to_string());
+        let mut words = line.split_whitespace();
+        if !words
+            .next()
+            .is_some_and(|command| command.eq_ignore_ascii_case("MENTIONS"))
+        {
+            return Err(invalid());
+        }
+        let count = match (words.next(), words.next()) {
+            (None, _) => DEFAULT_MENTIONS,
+            (Some(count), None) => count.parse().map_err(|_| invalid())?,
+            (Some(_), Some(_)) => return Err(invalid()),
+        };
+        Ok(MentionsCommand { count })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn composes_actions_and_text() {
+        let action = compose("chat", "/me  waves", 1);
+        assert_eq!(action.event, Some(Event::Action(Action {})));
+        assert_eq!(render(&action, "alice"), "* alice waves");
+
+        let text = compose("chat", "/metric units", 1);
+        assert_eq!(text.event, None);
+        assert_eq!(render(&text, "alice"), "<alice> /metric units");
+    }
+
+    #[test]
+    fn renders_each_kind_distinctly() {
+        assert_eq!(render(&join("chat", 1), "bob"), "--> bob joined chat");
+        assert_eq!(render(&leave("chat", 1), "bob"), "<-- bob left chat");
+        assert_eq!(
+            render(&notice("chat", "download finished", 1), "bob"),
+            "-- download finished"
+        );
+    }
+
+    #[test]
+    fn renders_binary_payloads_as_base64() {
+        let message = binary("chat", vec![0xff, 0x00, b'a', b'b'], 1);
+        assert_eq!(message.payload_type(), PayloadType::Binary);
+        assert_eq!(render(&message, "bob"), "<bob> [binary, 4 bytes] /wBhYg==");
+        assert_eq!(quote(&message, "bob"), "> bob: [binary, 4 bytes]");
+
+        for (data, encoded) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(base64(data), encoded);
+        }
+    }
+
+    #[test]
+    fn replies_reference_and_quote_their_parent() {
+        let parent = MessageId {
+            author: PeerId::random(),
+            seq: 4,
+        };
+        let reply = reply("chat", parent, "/me agrees", 1);
+        assert_eq!(reply.parent_id, Some(parent.into()));
+        assert_eq!(reply.event, Some(Event::Action(Action {})));
+
+        assert_eq!(quote(&compose("chat", "short", 1), "bob"), "> bob: short");
+        let long = compose("chat", &"é".repeat(QUOTE_LEN + 1), 1);
+        assert_eq!(
+            quote(&long, "bob"),
+            format!("> bob: {}…", "é".repeat(QUOTE_LEN))
+        );
+    }
+
+    #[test]
+    fn parses_reply_commands() {
+        let parent = MessageId {
+            author: PeerId::random(),
+            seq: 4,
+        };
+        assert_eq!(
+            format!("reply {parent}  sounds  good ")
+                .parse::<ReplyCommand>()
+                .unwrap(),
+            ReplyCommand {
+                parent,
+                text: "sounds  good".into()
+            }
+        );
+        for invalid in [
+            "REPLY".to_string(),
+            format!("REPLY {parent}"),
+            format!("REPLY {parent}   "),
+            format!("SAY {parent} hi"),
+            "REPLY nobody/1 hi".to_string(),
+        ] {
+            assert!(invalid.parse::<ReplyCommand>().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn resolves_mentions() {
+        let (alice, bob) = (PeerId::random(), PeerId::random());
+        let nicknames = HashMap::from([("alice".to_string(), alice), ("bob".to_string(), bob)]);
+        let message = mention(
+            compose("chat", "@bob, ask @alice and @bob (cc @carol) a@b @", 1),
+            &nicknames,
+        );
+
+        assert_eq!(
+            mentioned_nicknames(&message.body),
+            ["bob", "alice", "bob", "carol"]
+        );
+        assert_eq!(message.mentions, vec![bob.to_bytes(), alice.to_bytes()]);
+        assert!(mentions(&message, &alice));
+        assert!(!mentions(&message, &PeerId::random()));
+    }
+
+    #[test]
+    fn parses_mentions_commands() {
+        assert_eq!(
+            "mentions".parse::<MentionsCommand>().unwrap(),
+            MentionsCommand {
+                count: DEFAULT_MENTIONS
+            }
+        );
+        assert_eq!(
+            "MENTIONS 5".parse::<MentionsCommand>().unwrap(),
+            MentionsCommand { count: 5 }
+        );
+        for invalid in ["", "MENTIONS x", "MENTIONS 5 6", "MENTION"] {
+            assert!(invalid.parse::<MentionsCommand>().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn presence_is_noise() {
+        assert!(is_presence(&join("chat", 1)));
+        assert!(is_presence(&leave("chat", 1)));
+        assert!(!is_presence(&compose("chat", "/me waves", 1)));
+        assert!(!is_presence(&notice("chat", "hi", 1)));
+        assert!(!is_presence(&compose("chat", "hi", 1)));
+    }
+}
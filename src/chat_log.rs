@@ -0,0 +1,266 @@
+use libp2p::PeerId;
+use prost::Message;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::wire::{ChatEnvelope, MessageRef};
+use crate::NodeError;
+
+/// Returns the hash the author's next message links to.
+pub fn message_hash(message: &ChatEnvelope) -> [u8; 32] {
+    Sha256::digest(message.encode_to_vec()).into()
+}
+
+/// Identifies a chat message by its author and position in the author's
+/// chain, written `<peer-id>/<seq>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MessageId {
+    pub author: PeerId,
+    pub seq: u64,
+}
+
+impl MessageId {
+    /// Returns the ID of `message`, `None` if it names no valid author.
+    pub fn of(message: &ChatEnvelope) -> Option<Self> {
+        Some(MessageId {
+            author: PeerId::from_bytes(&message.author).ok()?,
+            seq: message.seq,
+        })
+    }
+}
+
+impl From<MessageId> for MessageRef {
+    fn from(id: MessageId) -> Self {
+        MessageRef {
+            author: id.author.to_bytes(),
+            seq: id.seq,
+        }
+    }
+}
+
+impl TryFrom<&MessageRef> for MessageId {
+    type Error = NodeError;
+
+    fn try_from(r: &MessageRef) -> Result<Self, NodeError> {
+        let author = PeerId::from_bytes(&r.author)
+            .map_err(|e| NodeError::Protocol(format!("invalid message author: {e}")))?;
+        Ok(MessageId { author, seq: r.seq })
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.author, self.seq)
+    }
+}
+
+impl FromStr for MessageId {
+    type Err = NodeError;
+
+    fn from_str(s: &str) -> Result<Self, NodeError> {
+        let invalid =
+            || NodeError::Command(format!("invalid message ID {s}, expected <peer-id>/<seq>"));
+        let (author, seq) = s.split_once('/').ok_or_else(invalid)?;
+        Ok(MessageId {
+            author: author.parse().map_err(|_| invalid())?,
+            seq: seq.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Numbers and links the messages published by the local peer.
+#[derive(Debug, Clone)]
+pub struct ChatChain {
+    author: Vec<u8>,
+    next_seq: u64,
+    prev_hash: Vec<u8>,
+}
+
+impl ChatChain {
+    /// Starts a new chain for `author`.
+    pub fn new(author: PeerId) -> Self {
+        ChatChain {
+            author: author.to_bytes(),
+            next_seq: 0,
+            prev_hash: Vec::new(),
+        }
+    }
+
+    /// Continues the chain after `last`, the latest message the local peer
+    /// published, e.g. after a restart.
+    pub fn resume(last: &ChatEnvelope) -> Self {
+        ChatChain {
+            author: last.author.clone(),
+            next_seq: last.seq + 1,
+            prev_hash: message_hash(last).to_vec(),
+        }
+    }
+
+    /// Makes `message` the next message of the chain, filling in its author,
+    /// sequence number and link.
+    pub fn link(&mut self, message: ChatEnvelope) -> ChatEnvelope {
+        let message = ChatEnvelope {
+            author: self.author.clone(),
+            seq: self.next_seq,
+            prev_hash: self.prev_hash.clone(),
+            ..message
+        };
+        self.next_seq += 1;
+        self.prev_hash = message_hash(&message).to_vec();
+        message
+    }
+}
+
+/// Outcome of checking an author's hash chain.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HistoryReport {
+    /// Number of distinct messages by the author.
+    pub messages: usize,
+    /// Ranges of sequence numbers missing before a later message.
+    pub gaps: Vec<RangeInclusive<u64>>,
+    /// Sequence numbers with more than one distinct message.
+    pub forks: Vec<u64>,
+    /// Sequence numbers whose message does not link to its predecessor.
+    pub broken_links: Vec<u64>,
+}
+
+impl HistoryReport {
+    pub fn is_intact(&self) -> bool {
+        self.gaps.is_empty() && self.forks.is_empty() && self.broken_links.is_empty()
+    }
+}
+
+/// Checks the hash chain of `author`'s messages among `messages`.
+///
+/// Messages by other authors are ignored and receiving the same message
+/// twice is not a fork. Messages missing after the latest one cannot be
+/// detected.
+pub fn verify_history<'a>(
+    author: &PeerId,
+    messages: impl IntoIterator<Item = &'a ChatEnvelope>,
+) -> HistoryReport {
+    let author = author.to_bytes();
+    let mut by_seq: BTreeMap<u64, BTreeMap<[u8; 32], &ChatEnvelope>> = BTreeMap::new();
+    for message in messages.into_iter().filter(|m| m.author == author) {
+        by_seq
+            .entry(message.seq)
+            .or_default()
+            .insert(message_hash(message), message);
+    }
+
+    let mut report = HistoryReport::default();
+    let mut expected = 0;
+    for (&seq, versions) in &by_seq {
+        report.messages += versions.len();
+        if versions.len() > 1 {
+            report.forks.push(seq);
+        }
+        if seq > expected {
+            report.gaps.push(expected..=seq - 1);
+        }
+        let linked = |m: &ChatEnvelope| match seq.checked_sub(1) {
+            None => m.prev_hash.is_empty(),
+            Some(prev) => by_seq
+                .get(&prev)
+                .is_none_or(|prev| prev.keys().any(|hash| m.prev_hash == hash[..])),
+        };
+        if !versions.values().all(|m| linked(m)) {
+            report.broken_links.push(seq);
+        }
+        expected = seq + 1;
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat;
+
+    fn chain(author: PeerId, len: usize) -> Vec<ChatEnvelope> {
+        let mut chain = ChatChain::new(author);
+        (0..len)
+            .map(|i| chain.link(chat::compose("chat", &format!("message {i}"), i as u64)))
+            .collect()
+    }
+
+    #[test]
+    fn intact_chain_verifies() {
+        let author = PeerId::random();
+        let mut messages = chain(author, 4);
+        messages.extend(chain(PeerId::random(), 2));
+        messages.push(messages[1].clone());
+
+        let report = verify_history(&author, &messages);
+        assert!(report.is_intact(), "{report:?}");
+        assert_eq!(report.messages, 4);
+    }
+
+    #[test]
+    fn resumed_chain_continues_links() {
+        let author = PeerId::random();
+        let mut messages = chain(author, 2);
+        let mut resumed = ChatChain::resume(&messages[1]);
+        messages.push(resumed.link(chat::leave("chat", 10)));
+
+        assert_eq!(messages[2].seq, 2);
+        assert!(verify_history(&author, &messages).is_intact());
+    }
+
+    #[test]
+    fn reports_gaps() {
+        let author = PeerId::random();
+        let messages = chain(author, 6);
+        let kept = [&messages[2], &messages[3], &messages[5]];
+
+        let report = verify_history(&author, kept);
+        assert_eq!(report.gaps, vec![0..=1, 4..=4]);
+        assert!(report.forks.is_empty());
+        assert!(report.broken_links.is_empty());
+    }
+
+    #[test]
+    fn reports_forks_and_rewritten_messages() {
+        let author = PeerId::random();
+        let mut messages = chain(author, 3);
+        let mut rewritten = messages[1].clone();
+        rewritten.body = "something else".into();
+        messages.push(rewritten);
+
+        let report = verify_history(&author, &messages);
+        assert_eq!(report.forks, vec![1]);
+        assert!(report.broken_links.is_empty());
+
+        // Without the original, message 2 no longer links to message 1.
+        messages.remove(1);
+        let report = verify_history(&author, &messages);
+        assert!(report.forks.is_empty());
+        assert_eq!(report.broken_links, vec![2]);
+    }
+
+    #[test]
+    fn message_ids_round_trip() {
+        let message = chain(PeerId::random(), 3).pop().unwrap();
+        let id = MessageId::of(&message).unwrap();
+        assert_eq!(id.seq, 2);
+
+        assert_eq!(id.to_string().parse::<MessageId>().unwrap(), id);
+        assert_eq!(MessageId::try_from(&MessageRef::from(id)).unwrap(), id);
+        for invalid in ["", "12", "notapeer/1", &format!("{}/x", id.author)] {
+            assert!(invalid.parse::<MessageId>().is_err(), "{invalid:?}");
+        }
+        assert!(MessageId::of(&ChatEnvelope::default()).is_none());
+    }
+
+    #[test]
+    fn first_message_must_not_link() {
+        let author = PeerId::random();
+        let mut messages = chain(author, 1);
+        messages[0].prev_hash = vec![0; 32];
+
+        assert_eq!(verify_history(&author, &messages).broken_links, vec![0]);
+    }
+}
@@ -0,0 +1,374 @@
+use libp2p::PeerId;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::wire::DocumentOp;
+use crate::NodeError;
+
+/// Topic the edits of the shared document are published on.
+pub const DOCUMENT_TOPIC: &str = "document";
+
+/// Highest Lamport clock of an edit. Local edits stop advancing the clock
+/// here instead of overflowing, and remote edits beyond it are rejected.
+const MAX_CLOCK: u64 = u64::MAX - 1;
+
+/// A key-value document replicated between peers as a last-writer-wins map.
+///
+/// Local edits return the [`DocumentOp`] to publish. Applying remote edits
+/// in any order, any number of times, leads every replica to the same state.
+/// Concurrent edits of a key are ordered by Lamport clock, then by author and
+/// finally by content, so that even an author publishing two edits with the
+/// same clock cannot make replicas diverge.
+#[derive(Debug, Clone)]
+pub struct Document {
+    author: Vec<u8>,
+    clock: u64,
+    /// Latest edit of each key, removals included so that older edits
+    /// arriving late do not resurrect the key.
+    entries: BTreeMap<String, DocumentOp>,
+}
+
+impl Document {
+    /// Creates an empty replica edited by `author`.
+    pub fn new(author: PeerId) -> Self {
+        Document {
+            author: author.to_bytes(),
+            clock: 0,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Recreates the replica of `author` from `ops`, the edits it held before
+    /// e.g. a restart. Its clock continues after the latest of them, so that
+    /// new local edits win over all restored ones.
+    pub fn restore<'a>(
+        author: PeerId,
+        ops: impl IntoIterator<Item = &'a DocumentOp>,
+    ) -> Result<Self, NodeError> {
+        let mut document = Document::new(author);
+        for op in ops {
+            document.apply(op)?;
+        }
+        Ok(document)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> DocumentOp {
+        self.edit(key, value, false)
+    }
+
+    pub fn remove(&mut self, key: &str) -> DocumentOp {
+        self.edit(key, "", true)
+    }
+
+    fn edit(&mut self, key: &str, value: &str, removed: bool) -> DocumentOp {
+        self.clock = (self.clock + 1).min(MAX_CLOCK);
+        let op = DocumentOp {
+            key: key.to_string(),
+            value: value.to_string(),
+            removed,
+            clock: self.clock,
+            author: self.author.clone(),
+        };
+        self.entries.insert(op.key.clone(), op.clone());
+        op
+    }
+
+    /// Applies an edit received from a peer. Returns whether it changed the
+    /// document.
+    pub fn apply(&mut self, op: &DocumentOp) -> Result<bool, NodeError> {
+        if op.clock > MAX_CLOCK {
+            return Err(NodeError::Protocol(format!(
+                "edit of {} has an exhausted clock",
+                op.key
+            )));
+        }
+        Ok(self.adopt(op))
+    }
+
+    /// Merges the state of another replica into this one.
+    pub fn merge(&mut self, other: &Document) {
+        // The other replica only holds edits with valid clocks.
+        for op in other.ops() {
+            self.adopt(op);
+        }
+    }
+
+    fn adopt(&mut self, op: &DocumentOp) -> bool {
+        self.clock = self.clock.max(op.clock);
+        match self.entries.get(&op.key) {
+            Some(current) if rank(current) >= rank(op) => false,
+            _ => {
+                self.entries.insert(op.key.clone(), op.clone());
+                true
+            }
+        }
+    }
+
+    /// Returns the edits making up the current state, e.g. to bring a peer
+    /// that just subscribed up to date.
+    pub fn ops(&self) -> impl Iterator<Item = &DocumentOp> {
+        self.entries.values()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .get(key)
+            .filter(|op| !op.removed)
+            .map(|op| op.value.as_str())
+    }
+
+    /// Returns the keys and values of the document, ordered by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .values()
+            .filter(|op| !op.removed)
+            .map(|op| (op.key.as_str(), op.value.as_str()))
+    }
+}
+
+/// Orders the edits of a key, the highest one winning.
+fn rank(op: &DocumentOp) -> (u64, &[u8], bool, &str) {
+    (op.clock, &op.author, op.removed, &op.value)
+}
+
+/// Lists the document one `key = value` line per key, as `SHOW` prints it.
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in self.iter() {
+            writeln!(f, "{key} = {value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A command editing or showing the shared document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DocumentCommand {
+    /// `SET <key> <value>`, the value being the rest of the line.
+    Set { key: String, value: String },
+    /// `REMOVE <key>`.
+    Remove { key: String },
+    /// `SHOW`.
+    Show,
+}
+
+impl FromStr for DocumentCommand {
+    type Err = NodeError;
+
+    fn from_str(line: &str) -> Result<Self, NodeError> {
+        let invalid = || NodeError::Command(line.to_string());
+        let line = line.trim();
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim_start();
+        match command.to_ascii_uppercase().as_str() {
+            "SET" => {
+                let (key, value) = args.split_once(' ').ok_or_else(invalid)?;
+                Ok(DocumentCommand::Set {
+                    key: key.to_string(),
+                    value: value.trim_start().to_string(),
+                })
+            }
+            "REMOVE" if !args.is_empty() && !args.contains(' ') => Ok(DocumentCommand::Remove {
+                key: args.to_string(),
+            }),
+            "SHOW" if args.is_empty() => Ok(DocumentCommand::Show),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_edits_converge() {
+        let (mut alice, mut bob) = (
+            Document::new(PeerId::random()),
+            Document::new(PeerId::random()),
+        );
+        let ops = vec![
+            alice.set("milk", "1 l"),
+            bob.set("milk", "2 l"),
+            bob.set("eggs", "6"),
+            alice.set("bread", "1"),
+            alice.remove("bread"),
+        ];
+
+        for op in &ops {
+            alice.apply(op).unwrap();
+        }
+        for op in ops.iter().rev() {
+            bob.apply(op).unwrap();
+        }
+
+        assert_eq!(alice.to_string(), bob.to_string());
+        assert_eq!(alice.get("eggs"), Some("6"));
+        assert_eq!(alice.get("bread"), None);
+        assert!(alice.get("milk").is_some());
+    }
+
+    #[test]
+    fn later_edits_win() {
+        let mut alice = Document::new(PeerId::random());
+        let mut bob = Document::new(PeerId::random());
+        let first = alice.set("milk", "1 l");
+        bob.apply(&first).unwrap();
+        let second = bob.set("milk", "2 l");
+
+        assert!(alice.apply(&second).unwrap());
+        assert!(!alice.apply(&first).unwrap(), "an old edit must not win");
+        assert!(
+            !alice.apply(&second).unwrap(),
+            "applying twice must not change anything"
+        );
+        assert_eq!(alice.get("milk"), Some("2 l"));
+    }
+
+    #[test]
+    fn removals_are_not_undone_by_late_edits() {
+        let mut alice = Document::new(PeerId::random());
+        let mut bob = Document::new(PeerId::random());
+        let set = alice.set("milk", "1 l");
+        bob.apply(&set).unwrap();
+        let remove = bob.remove("milk");
+
+        let mut carol = Document::new(PeerId::random());
+        carol.apply(&remove).unwrap();
+        carol.apply(&set).unwrap();
+        assert_eq!(carol.get("milk"), None);
+        assert_eq!(carol.to_string(), "");
+    }
+
+    #[test]
+    fn merge_is_commutative() {
+        let mut alice = Document::new(PeerId::random());
+        let mut bob = Document::new(PeerId::random());
+        alice.set("milk", "1 l");
+        alice.set("eggs", "6");
+        bob.set("milk", "2 l");
+        bob.remove("eggs");
+
+        let mut ab = alice.clone();
+        ab.merge(&bob);
+        let mut ba = bob.clone();
+        ba.merge(&alice);
+        assert_eq!(ab.to_string(), ba.to_string());
+
+        // Edits after a merge win over everything merged.
+        let op = ab.set("milk", "3 l");
+        ba.apply(&op).unwrap();
+        assert_eq!(ba.get("milk"), Some("3 l"));
+    }
+
+    #[test]
+    fn equal_clocks_of_one_author_converge() {
+        let mut alice = Document::new(PeerId::random());
+        let set = alice.set("milk", "1 l");
+        let conflicting = [
+            DocumentOp {
+                value: "2 l".into(),
+                ..set.clone()
+            },
+            DocumentOp {
+                value: String::new(),
+                removed: true,
+                ..set.clone()
+            },
+        ];
+
+        let mut bob = Document::new(PeerId::random());
+        let mut carol = Document::new(PeerId::random());
+        for op in [&set, &conflicting[0], &conflicting[1]] {
+            bob.apply(op).unwrap();
+        }
+        for op in [&conflicting[1], &conflicting[0], &set] {
+            carol.apply(op).unwrap();
+        }
+        assert_eq!(
+            bob.ops().collect::<Vec<_>>(),
+            carol.ops().collect::<Vec<_>>()
+        );
+        assert_eq!(bob.get("milk"), None);
+    }
+
+    #[test]
+    fn rejects_exhausted_clocks() {
+        let mut alice = Document::new(PeerId::random());
+        let mut bob = Document::new(PeerId::random());
+        let mut op = bob.set("milk", "1 l");
+        op.clock = u64::MAX;
+        assert!(matches!(alice.apply(&op), Err(NodeError::Protocol(_))));
+        assert_eq!(alice.get("milk"), None);
+
+        op.clock = MAX_CLOCK;
+        assert!(alice.apply(&op).unwrap());
+        // Local edits stay at the highest clock instead of overflowing.
+        assert_eq!(alice.set("eggs", "6").clock, MAX_CLOCK);
+        assert_eq!(alice.set("eggs", "12").clock, MAX_CLOCK);
+    }
+
+    #[test]
+    fn restored_replicas_keep_counting() {
+        let author = PeerId::random();
+        let mut alice = Document::new(author);
+        alice.set("milk", "1 l");
+        alice.set("milk", "2 l");
+        alice.set("eggs", "6");
+        let mut bob = alice.clone();
+        let saved: Vec<_> = alice.ops().cloned().collect();
+
+        let mut restarted = Document::restore(author, &saved).unwrap();
+        assert_eq!(restarted.to_string(), alice.to_string());
+        let op = restarted.set("milk", "3 l");
+        assert!(bob.apply(&op).unwrap(), "edits after a restart must win");
+        assert_eq!(bob.get("milk"), Some("3 l"));
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            "SET milk 2 l".parse::<DocumentCommand>().unwrap(),
+            DocumentCommand::Set {
+                key: "milk".into(),
+                value: "2 l".into()
+            }
+        );
+        assert_eq!(
+            "remove  milk".parse::<DocumentCommand>().unwrap(),
+            DocumentCommand::Remove { key: "milk".into() }
+        );
+        assert_eq!(
+            " show ".parse::<DocumentCommand>().unwrap(),
+            DocumentCommand::Show
+        );
+        for invalid in [
+            "",
+            "SET milk",
+            "REMOVE",
+            "REMOVE a b",
+            "SHOW all",
+            "GET milk",
+        ] {
+            assert!(
+                matches!(
+                    invalid.parse::<DocumentCommand>(),
+                    Err(NodeError::Command(_))
+                ),
+                "{invalid:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn shows_keys_in_order() {
+        let mut document = Document::new(PeerId::random());
+        document.set("milk", "1 l");
+        document.set("eggs", "6");
+        document.set("bread", "1");
+        document.remove("bread");
+
+        assert_eq!(document.to_string(), "eggs = 6\nmilk = 1 l\n");
+    }
+}
@@ -0,0 +1,41 @@
+use libp2p::identity::error::SigningError;
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Errors returned by the node's library APIs.
+#[derive(Debug, Error)]
+pub enum NodeError {
+    #[error("protocol error: {0}")]
+    Protocol(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("malformed message: {0}")]
+    Decode(#[from] prost::DecodeError),
+    #[error("no test vector is named {0}")]
+    UnknownVector(String),
+    #[error("sample {name} does not match the test vector: {reason}")]
+    VectorMismatch { name: String, reason: String },
+    #[error("announcement of {name} does not fit into a message")]
+    AnnouncementTooLarge { name: String },
+    #[error("announcement of {name} has an invalid signature")]
+    InvalidSignature { name: String },
+    #[error("announcement of {name} has expired")]
+    AnnouncementExpired { name: String },
+    #[error("signing failed: {0}")]
+    Signing(#[from] SigningError),
+    #[error("no download of {0} is in progress")]
+    NotOpen(String),
+    #[error("content of {name} does not match the expected hash")]
+    HashMismatch { name: String },
+    #[error("download {name} exceeds the maximum file size")]
+    FileTooLarge { name: String },
+    #[error("download name {0} is reserved")]
+    ReservedName(String),
+    #[error("path {} is outside of the permitted directory", .0.display())]
+    PathOutsideSandbox(PathBuf),
+    #[error("invalid command: {0}")]
+    Command(String),
+}
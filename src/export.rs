@@ -0,0 +1,362 @@
+//! Transcripts of a topic's chat history, e.g. for notes after a workshop.
+
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::chat;
+use crate::chat_log::MessageId;
+use crate::store::Store;
+use crate::wire::chat_envelope::{Event, PayloadType};
+use crate::wire::ChatEnvelope;
+use crate::NodeError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Markdown,
+    Html,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = NodeError;
+
+    fn from_str(s: &str) -> Result<Self, NodeError> {
+        match s {
+            "md" => Ok(Format::Markdown),
+            "html" => Ok(Format::Html),
+            "json" => Ok(Format::Json),
+            _ => Err(NodeError::Command(format!(
+                "unknown transcript format {s}, expected md, html or json"
+            ))),
+        }
+    }
+}
+
+/// Renders the whole stored history of `topic`, naming authors by the
+/// nickname the store knows them by.
+pub fn export_chat(store: &Store, topic: &str, format: Format) -> Result<String, NodeError> {
+    // SQLite's largest LIMIT.
+    let messages = store.chat_history(topic, i64::MAX as usize)?;
+    let nicknames = store
+        .peers()?
+        .into_iter()
+        .filter_map(|peer| Some((peer.peer_id, peer.nickname?)))
+        .collect();
+    Ok(transcript(topic, &messages, &nicknames, format))
+}
+
+/// Renders `messages`, oldest first, as a transcript of `topic`. Replies
+/// quote their parent if it is among `messages`.
+pub fn transcript(
+    topic: &str,
+    messages: &[ChatEnvelope],
+    nicknames: &HashMap<PeerId, String>,
+    format: Format,
+) -> String {
+    let author = |message: &ChatEnvelope| match PeerId::from_bytes(&message.author) {
+        Ok(peer_id) => nicknames
+            .get(&peer_id)
+            .cloned()
+            .unwrap_or_else(|| peer_id.to_string()),
+        Err(_) => "unknown".to_string(),
+    };
+    let by_id: HashMap<_, _> = messages
+        .iter()
+        .filter_map(|message| Some((MessageId::of(message)?, message)))
+        .collect();
+    // The line to show above a reply.
+    let quote = |message: &ChatEnvelope| {
+        let parent = message.parent_id.as_ref()?;
+        let parent = MessageId::try_from(parent)
+            .ok()
+            .and_then(|id| by_id.get(&id));
+        Some(match parent {
+            Some(parent) => chat::quote(parent, &author(parent)),
+            None => "> (message not available)".to_string(),
+        })
+    };
+
+    let mut out = String::new();
+    match format {
+        Format::Markdown => {
+            writeln!(out, "# Chat transcript of {}\n", escape_markdown(topic)).unwrap();
+            for message in messages {
+                if let Some(quote) = quote(message) {
+                    writeln!(out, "- {}", escape_markdown(&quote)).unwrap();
+                }
+                let line = chat::render(message, &author(message));
+                writeln!(
+                    out,
+                    "- `{}` {}",
+                    utc(message.sent_at),
+                    escape_markdown(&line)
+                )
+                .unwrap();
+            }
+        }
+        Format::Html => {
+            let topic = escape_html(topic);
+            writeln!(out, "<!DOCTYPE html>").unwrap();
+            writeln!(out, "<meta charset=\"utf-8\">").unwrap();
+            writeln!(out, "<title>Chat transcript of {topic}</title>").unwrap();
+            writeln!(out, "<h1>Chat transcript of {topic}</h1>").unwrap();
+            writeln!(out, "<ul>").unwrap();
+            for message in messages {
+                if let Some(quote) = quote(message) {
+                    writeln!(
+                        out,
+                        "<li><blockquote>{}</blockquote></li>",
+                        escape_html(&quote)
+                    )
+                    .unwrap();
+                }
+                let line = chat::render(message, &author(message));
+                writeln!(
+                    out,
+                    "<li><time>{}</time> {}</li>",
+                    utc(message.sent_at),
+                    escape_html(&line)
+                )
+                .unwrap();
+            }
+            writeln!(out, "</ul>").unwrap();
+        }
+        Format::Json => {
+            let entries: Vec<_> = messages
+                .iter()
+                .map(|message| {
+                    let reply_to = match &message.parent_id {
+                        Some(parent) => match MessageId::try_from(parent) {
+                            Ok(id) => format!(",\"reply_to\":\"{id}\""),
+                            Err(_) => String::new(),
+                        },
+                        None => String::new(),
+                    };
+                    let data = match message.payload_type() {
+                        PayloadType::Binary => format!(",\"data\":\"{}\"", chat::base64(&message.data)),
+                        _ => String::new(),
+                    };
+                    format!(
+                        "{{\"sent_at\":{},\"author\":{},\"kind\":\"{}\",\"body\":{}{data}{reply_to}}}",
+                        message.sent_at,
+                        json_string(&author(message)),
+                        kind(message),
+                        json_string(&message.body)
+                    )
+                })
+                .collect();
+            writeln!(
+                out,
+                "{{\"topic\":{},\"messages\":[{}]}}",
+                json_string(topic),
+                entries.join(",")
+            )
+            .unwrap();
+        }
+    }
+    out
+}
+
+fn kind(message: &ChatEnvelope) -> &'static str {
+    match message.event {
+        None => "text",
+        Some(Event::Action(_)) => "action",
+        Some(Event::Join(_)) => "join",
+        Some(Event::Leave(_)) => "leave",
+        Some(Event::Notice(_)) => "notice",
+    }
+}
+
+/// Formats seconds since the Unix epoch as a UTC date and time.
+fn utc(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+    // Howard Hinnant's days-to-civil algorithm.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_{}[]()<>#+-.!|~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat_log::ChatChain;
+    use crate::store::PeerRecord;
+    use std::time::UNIX_EPOCH;
+
+    fn history(alice: PeerId) -> Vec<ChatEnvelope> {
+        let mut chain = ChatChain::new(alice);
+        vec![
+            chain.link(chat::join("chat", 1_665_000_000)),
+            chain.link(chat::compose(
+                "chat",
+                "<b>5 * 3</b> & \"more\"",
+                1_665_000_061,
+            )),
+            chain.link(chat::compose("chat", "/me waves", 1_665_003_599)),
+        ]
+    }
+
+    #[test]
+    fn formats_utc_timestamps() {
+        assert_eq!(utc(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(utc(951_782_400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(utc(1_665_003_599), "2022-10-05 20:59:59 UTC");
+    }
+
+    #[test]
+    fn exports_markdown_with_nicknames() {
+        let store = Store::open_in_memory().unwrap();
+        let alice = PeerId::random();
+        store
+            .upsert_peer(&PeerRecord {
+                peer_id: alice,
+                nickname: Some("alice".into()),
+                addresses: vec![],
+                last_seen: UNIX_EPOCH,
+            })
+            .unwrap();
+        for message in history(alice) {
+            store.append_chat(&message).unwrap();
+        }
+
+        assert_eq!(
+            export_chat(&store, "chat", Format::Markdown).unwrap(),
+            "# Chat transcript of chat\n\n\
+             - `2022-10-05 20:00:00 UTC` \\-\\-\\> alice joined chat\n\
+             - `2022-10-05 20:01:01 UTC` \\<alice\\> \\<b\\>5 \\* 3\\</b\\> & \"more\"\n\
+             - `2022-10-05 20:59:59 UTC` \\* alice waves\n"
+        );
+    }
+
+    #[test]
+    fn exports_escaped_html() {
+        let alice = PeerId::random();
+        let html = transcript("chat", &history(alice), &HashMap::new(), Format::Html);
+
+        assert!(html.contains(&format!(
+            "<li><time>2022-10-05 20:01:01 UTC</time> &lt;{alice}&gt; \
+             &lt;b&gt;5 * 3&lt;/b&gt; &amp; &quot;more&quot;</li>"
+        )));
+        assert!(!html.contains("<b>"));
+    }
+
+    #[test]
+    fn exports_escaped_json() {
+        let alice = PeerId::random();
+        let nicknames = HashMap::from([(alice, "al\"ice\n".to_string())]);
+        let json = transcript("chat", &history(alice)[1..2], &nicknames, Format::Json);
+
+        assert_eq!(
+            json,
+            "{\"topic\":\"chat\",\"messages\":[{\"sent_at\":1665000061,\
+             \"author\":\"al\\\"ice\\n\",\"kind\":\"text\",\
+             \"body\":\"<b>5 * 3</b> & \\\"more\\\"\"}]}\n"
+        );
+    }
+
+    #[test]
+    fn replies_quote_their_parent() {
+        let alice = PeerId::random();
+        let mut messages = history(alice);
+        let parent = MessageId::of(&messages[1]).unwrap();
+        messages.push(chat::reply("chat", parent, "it is 15", 1_665_003_600));
+        messages.push(chat::reply(
+            "chat",
+            MessageId { seq: 9, ..parent },
+            "what?",
+            1_665_003_601,
+        ));
+        let nicknames = HashMap::from([(alice, "alice".to_string())]);
+
+        let markdown = transcript("chat", &messages, &nicknames, Format::Markdown);
+        assert!(markdown.contains(
+            "- \\> alice: \\<b\\>5 \\* 3\\</b\\> & \"more\"\n\
+             - `2022-10-05 21:00:00 UTC` \\<unknown\\> it is 15\n"
+        ));
+        assert!(markdown.contains("- \\> \\(message not available\\)\n"));
+
+        let html = transcript("chat", &messages, &nicknames, Format::Html);
+        assert!(html.contains("<li><blockquote>&gt; alice: &lt;b&gt;"));
+
+        let json = transcript("chat", &messages[3..4], &nicknames, Format::Json);
+        assert!(json.contains(&format!(",\"reply_to\":\"{parent}\"}}")));
+    }
+
+    #[test]
+    fn exports_binary_payloads_as_base64() {
+        let messages = [chat::binary("chat", vec![0, 1, 2], 1_665_000_000)];
+        let nicknames = HashMap::new();
+
+        let json = transcript("chat", &messages, &nicknames, Format::Json);
+        assert!(json.contains(",\"body\":\"\",\"data\":\"AAEC\"}"));
+        let markdown = transcript("chat", &messages, &nicknames, Format::Markdown);
+        assert!(markdown.contains("\\<unknown\\> \\[binary, 3 bytes\\] AAEC\n"));
+    }
+
+    #[test]
+    fn parses_formats() {
+        assert_eq!("md".parse::<Format>().unwrap(), Format::Markdown);
+        assert_eq!("html".parse::<Format>().unwrap(), Format::Html);
+        assert_eq!("json".parse::<Format>().unwrap(), Format::Json);
+        assert!("pdf".parse::<Format>().is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::storage::PARTIAL_SUFFIX;
+
+/// Longest file name, in bytes, accepted by common filesystems.
+const MAX_NAME_LEN: usize = 255;
+
+/// Longest suffix after the last `.` still kept as an extension.
+const MAX_EXTENSION_LEN: usize = 32;
+
+/// Separates a rewritten name from the hash of the announced name.
+const TAG_SEPARATOR: char = '~';
+
+/// Names Windows reserves for devices, regardless of extension.
+const RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Maps a file name announced by a remote peer to a name that is safe to
+/// create on any common operating system.
+///
+/// The name is NFC normalized, path separators, characters reserved on
+/// Windows and control characters are replaced by `_`, trailing dots and
+/// spaces are dropped, reserved device names are prefixed with `_`, names
+/// ending in `.partial`, which unfinished downloads use, get `_` appended and
+/// the result is truncated to 255 bytes, keeping the extension where
+/// possible.
+///
+/// Names that had to be rewritten, or that contain `~`, get `~` and a short
+/// hash of the announced name appended to their stem, so different announced
+/// names never share a local name. Names differing only in case, which
+/// case-insensitive filesystems such as those of Windows and macOS treat as
+/// the same, are only told apart by a [`NameMap`], which also leads back from
+/// the local name to the announced one.
+pub fn local_name(announced: &str) -> String {
+    let mut name: String = announced
+        .nfc()
+        .map(|c| match c {
+            '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    let trimmed_len = name.trim_end_matches(['.', ' ']).len();
+    name.truncate(trimmed_len);
+    if name.is_empty() {
+        name.push('_');
+    }
+
+    let stem = name.split('.').next().unwrap_or_default();
+    if RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        name.insert(0, '_');
+    }
+    if name.to_lowercase().ends_with(PARTIAL_SUFFIX) {
+        name.push('_');
+    }
+
+    let rewritten =
+        name != announced || name.len() > MAX_NAME_LEN || announced.contains(TAG_SEPARATOR);
+    if !rewritten {
+        return name;
+    }
+    tag(announced, &name)
+}
+
+/// Appends `~` and a short hash of `announced` to the stem of `name`,
+/// truncating the stem to keep the result within 255 bytes.
+fn tag(announced: &str, name: &str) -> String {
+    let hash = Sha256::digest(announced.as_bytes());
+    let tag = format!("{TAG_SEPARATOR}{}", hex(&hash[..6]));
+
+    let (stem, extension) = match name.rfind('.') {
+        Some(i) if i > 0 && name.len() - i <= MAX_EXTENSION_LEN => name.split_at(i),
+        _ => (name, ""),
+    };
+    let mut end = (MAX_NAME_LEN - tag.len() - extension.len()).min(stem.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    let stem = match stem[..end].trim_end_matches(['.', ' ']) {
+        "" => "_",
+        stem => stem,
+    };
+    format!("{stem}{tag}{extension}")
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Records which local name each announced file name is stored under.
+///
+/// Persist the pairs, e.g. with [`Store::set_local_name`], and restore them
+/// with [`NameMap::insert_local`] so that files keep their local names.
+///
+/// [`Store::set_local_name`]: crate::store::Store::set_local_name
+#[derive(Debug, Clone, Default)]
+pub struct NameMap {
+    to_local: HashMap<String, String>,
+    to_announced: HashMap<String, String>,
+    /// Lowercased local names, to tell apart names differing only in case.
+    folded: HashMap<String, String>,
+}
+
+impl NameMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the local name of `announced`, choosing and remembering one if
+    /// it has none yet: its [`local_name`], tagged with a hash if another
+    /// local name only differs from it in case.
+    pub fn insert(&mut self, announced: &str) -> &str {
+        if !self.to_local.contains_key(announced) {
+            let mut local = local_name(announced);
+            if self.folded.contains_key(&local.to_lowercase()) {
+                local = tag(announced, &local);
+            }
+            self.insert_local(announced, &local);
+        }
+        &self.to_local[announced]
+    }
+
+    /// Remembers that `announced` is stored as `local`.
+    pub fn insert_local(&mut self, announced: &str, local: &str) {
+        self.to_local
+            .insert(announced.to_string(), local.to_string());
+        self.to_announced
+            .insert(local.to_string(), announced.to_string());
+        self.folded
+            .insert(local.to_lowercase(), announced.to_string());
+    }
+
+    pub fn local(&self, announced: &str) -> Option<&str> {
+        self.to_local.get(announced).map(String::as_str)
+    }
+
+    pub fn announced(&self, local: &str) -> Option<&str> {
+        self.to_announced.get(local).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_safe_names() {
+        assert_eq!(local_name("notes.pdf"), "notes.pdf");
+        assert_eq!(local_name("a_b"), "a_b");
+        assert_eq!(local_name(".hidden"), ".hidden");
+    }
+
+    #[test]
+    fn tags_rewritten_names() {
+        let name = local_name("a:b.txt");
+        assert!(name.starts_with("a_b~"), "{name}");
+        assert!(name.ends_with(".txt"), "{name}");
+
+        assert!(local_name("..").starts_with("_~"));
+        assert!(local_name("x\u{7}y. .").starts_with("x_y~"));
+    }
+
+    #[test]
+    fn distinct_names_do_not_collide() {
+        let names = ["a_b", "a:b", "a/b", "a\\b", "a_b~", "CON", "_CON", "con"];
+        let local: Vec<_> = names.iter().map(|n| local_name(n)).collect();
+        for (i, a) in local.iter().enumerate() {
+            for b in &local[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn escapes_partial_suffix() {
+        for announced in ["notes.partial", "notes.PARTIAL", "notes.pdf.partial"] {
+            let name = local_name(announced);
+            assert!(
+                name.ends_with(".partial_") || name.ends_with(".PARTIAL_"),
+                "{name}"
+            );
+            assert!(name.contains(TAG_SEPARATOR), "{name}");
+        }
+        assert_eq!(local_name("partial"), "partial");
+        assert_ne!(local_name("notes.partial"), local_name("notes.partial_"));
+    }
+
+    #[test]
+    fn escapes_reserved_device_names() {
+        assert!(local_name("CON").starts_with("_CON~"));
+        assert!(local_name("con.txt").starts_with("_con~"));
+        assert!(local_name("lpt9.tar.gz").starts_with("_lpt9.tar~"));
+        assert_eq!(local_name("CONSOLE"), "CONSOLE");
+    }
+
+    #[test]
+    fn normalizes_to_nfc() {
+        let decomposed = "Cafe\u{301}.txt";
+        let name = local_name(decomposed);
+        assert!(name.starts_with("Caf\u{e9}~"), "{name}");
+        assert_eq!(local_name("Caf\u{e9}.txt"), "Caf\u{e9}.txt");
+    }
+
+    #[test]
+    fn truncates_on_char_boundaries_keeping_extension() {
+        let name = local_name(&format!("{}.pdf", "\u{e9}".repeat(200)));
+        assert!(name.len() <= MAX_NAME_LEN);
+        assert!(name.ends_with(".pdf"));
+        assert!(name.starts_with('\u{e9}'));
+
+        let name = local_name(&"x".repeat(300));
+        assert!(name.len() <= MAX_NAME_LEN);
+        assert_ne!(name, local_name(&"x".repeat(301)));
+    }
+
+    #[test]
+    fn truncated_dot_stem_is_not_empty() {
+        let name = local_name(&format!("{}.txt", ".".repeat(300)));
+        assert!(name.starts_with("_~"), "{name}");
+        assert!(name.ends_with(".txt"));
+    }
+
+    #[test]
+    fn name_map_is_reversible() {
+        let mut map = NameMap::new();
+        let a = map.insert("a:b").to_string();
+        let b = map.insert("a/b").to_string();
+
+        assert_ne!(a, b);
+        assert_eq!(map.local("a:b"), Some(a.as_str()));
+        assert_eq!(map.announced(&a), Some("a:b"));
+        assert_eq!(map.announced(&b), Some("a/b"));
+        assert_eq!(map.announced("a_b"), None);
+        assert_eq!(map.insert("a:b"), a, "names are chosen once");
+    }
+
+    #[test]
+    fn name_map_tags_names_differing_in_case() {
+        let mut map = NameMap::new();
+        assert_eq!(map.insert("Notes.pdf"), "Notes.pdf");
+        let other = map.insert("notes.pdf").to_string();
+        assert!(other.starts_with("notes~"), "{other}");
+        assert!(other.ends_with(".pdf"), "{other}");
+        assert_eq!(map.announced(&other), Some("notes.pdf"));
+    }
+
+    #[test]
+    fn name_map_restores_recorded_names() {
+        let mut map = NameMap::new();
+        map.insert_local("notes.pdf", "notes (1).pdf");
+        assert_eq!(map.insert("notes.pdf"), "notes (1).pdf");
+        assert_eq!(map.announced("notes (1).pdf"), Some("notes.pdf"));
+    }
+}
@@ -0,0 +1,233 @@
+use async_std::path::{Path, PathBuf};
+use async_std::task;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::time::SystemTime;
+
+use crate::sandbox::Sandbox;
+use crate::NodeError;
+
+const BUF_LEN: usize = 64 * 1024;
+
+/// Number of hashes a [`HashCache`] keeps unless configured otherwise.
+pub const DEFAULT_HASH_CACHE_CAPACITY: usize = 4096;
+
+/// Computes the SHA-256 of the file at `path`, reading it incrementally and
+/// reporting the total number of bytes hashed so far to `progress`.
+///
+/// The work runs on the blocking thread pool so that hashing large files
+/// does not stall the tasks driving the network. `path` is used as is; it
+/// must already have been resolved through a [`Sandbox`].
+pub(crate) async fn sha256_file(
+    path: impl AsRef<Path>,
+    mut progress: impl FnMut(u64) + Send + 'static,
+) -> Result<[u8; 32], NodeError> {
+    let path = path.as_ref().to_path_buf();
+    task::spawn_blocking(move || {
+        let mut file = File::open(&path)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; BUF_LEN];
+        let mut hashed = 0;
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            hashed += n as u64;
+            progress(hashed);
+        }
+        Ok(hasher.finalize().into())
+    })
+    .await
+}
+
+/// Hashes shared files, remembering the results so unchanged files are not
+/// re-read.
+///
+/// Only files inside the share directory given to [`HashCache::new`] can be
+/// hashed. Entries are keyed by canonical path, invalidated when the file's
+/// size or modification time changes, and the least recently used entry is
+/// evicted once the cache is full.
+#[derive(Debug)]
+pub struct HashCache {
+    sandbox: Sandbox,
+    capacity: usize,
+    entries: HashMap<PathBuf, CacheEntry>,
+    /// Incremented on every lookup to order entries by last use.
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    len: u64,
+    modified: SystemTime,
+    hash: [u8; 32],
+    last_used: u64,
+}
+
+impl HashCache {
+    pub fn new(share_root: impl Into<PathBuf>) -> Self {
+        HashCache {
+            sandbox: Sandbox::new(share_root),
+            capacity: DEFAULT_HASH_CACHE_CAPACITY,
+            entries: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Returns the SHA-256 of the file at `path` within the share directory,
+    /// hashing it unless a cached hash is still valid.
+    pub async fn hash(
+        &mut self,
+        path: impl AsRef<Path>,
+        progress: impl FnMut(u64) + Send + 'static,
+    ) -> Result<[u8; 32], NodeError> {
+        let path = self.sandbox.resolve_existing(path).await?;
+        let metadata = path.metadata().await?;
+        let (len, modified) = (metadata.len(), metadata.modified()?);
+        self.clock += 1;
+
+        if let Some(entry) = self.entries.get_mut(&path) {
+            if entry.len == len && entry.modified == modified {
+                entry.last_used = self.clock;
+                return Ok(entry.hash);
+            }
+        }
+
+        let hash = sha256_file(&path, progress).await?;
+        if !self.entries.contains_key(&path) && self.entries.len() >= self.capacity {
+            self.evict_least_recently_used();
+        }
+        if self.capacity > 0 {
+            self.entries.insert(
+                path,
+                CacheEntry {
+                    len,
+                    modified,
+                    hash,
+                    last_used: self.clock,
+                },
+            );
+        }
+        Ok(hash)
+    }
+
+    /// Drops the cached hash of `path`, e.g. once it is no longer shared.
+    pub async fn forget(&mut self, path: impl AsRef<Path>) -> Result<(), NodeError> {
+        let path = self.sandbox.resolve_existing(path).await?;
+        self.entries.remove(&path);
+        Ok(())
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(path, _)| path.clone());
+        if let Some(path) = oldest {
+            self.entries.remove(&path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Returns a progress callback and the number of bytes it saw hashed.
+    fn counter() -> (impl FnMut(u64) + Send + 'static, Arc<AtomicU64>) {
+        let hashed = Arc::new(AtomicU64::new(0));
+        let seen = hashed.clone();
+        (move |n| seen.store(n, Ordering::SeqCst), hashed)
+    }
+
+    #[async_std::test]
+    async fn hashes_incrementally_with_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = vec![7; 3 * BUF_LEN + 1];
+        std::fs::write(dir.path().join("a"), &content).unwrap();
+
+        let (progress, hashed) = counter();
+        let hash = sha256_file(dir.path().join("a"), progress).await.unwrap();
+
+        assert_eq!(hash, <[u8; 32]>::from(Sha256::digest(&content)));
+        assert_eq!(hashed.load(Ordering::SeqCst), content.len() as u64);
+    }
+
+    #[async_std::test]
+    async fn cache_hits_until_metadata_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a");
+        std::fs::write(&path, b"hello").unwrap();
+        let mut cache = HashCache::new(dir.path());
+
+        let first = cache.hash("a", |_| {}).await.unwrap();
+        let (progress, hashed) = counter();
+        assert_eq!(cache.hash("./a", progress).await.unwrap(), first);
+        assert_eq!(hashed.load(Ordering::SeqCst), 0, "expected a cache hit");
+        assert_eq!(cache.entries.len(), 1);
+
+        // Same size, different content and modification time.
+        std::fs::write(&path, b"hellx").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        let (progress, hashed) = counter();
+        let second = cache.hash("a", progress).await.unwrap();
+        assert_ne!(second, first);
+        assert_eq!(hashed.load(Ordering::SeqCst), 5, "expected a re-hash");
+
+        std::fs::write(&path, b"hello!").unwrap();
+        assert_ne!(cache.hash("a", |_| {}).await.unwrap(), second);
+    }
+
+    #[async_std::test]
+    async fn cache_evicts_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a", "b", "c"] {
+            std::fs::write(dir.path().join(name), name).unwrap();
+        }
+        let mut cache = HashCache::new(dir.path()).with_capacity(2);
+
+        cache.hash("a", |_| {}).await.unwrap();
+        cache.hash("b", |_| {}).await.unwrap();
+        cache.hash("a", |_| {}).await.unwrap();
+        cache.hash("c", |_| {}).await.unwrap();
+
+        let root = async_std::fs::canonicalize(dir.path()).await.unwrap();
+        assert_eq!(cache.entries.len(), 2);
+        assert!(cache.entries.contains_key(&root.join("a")));
+        assert!(!cache.entries.contains_key(&root.join("b")));
+
+        cache.forget("a").await.unwrap();
+        assert!(!cache.entries.contains_key(&root.join("a")));
+    }
+
+    #[async_std::test]
+    async fn cache_only_hashes_shared_files() {
+        let share = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret"), b"secret").unwrap();
+        let mut cache = HashCache::new(share.path());
+
+        assert!(matches!(
+            cache.hash(outside.path().join("secret"), |_| {}).await,
+            Err(NodeError::PathOutsideSandbox(_))
+        ));
+    }
+}
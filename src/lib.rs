@@ -0,0 +1,16 @@
+pub mod announce;
+pub mod chat;
+pub mod chat_log;
+pub mod document;
+pub mod error;
+pub mod export;
+pub mod filename;
+pub mod hash;
+pub mod reaction;
+pub mod sandbox;
+pub mod storage;
+pub mod store;
+pub mod test_vectors;
+pub mod wire;
+
+pub use error::NodeError;
@@ -1,16 +1,139 @@
-use clap::Parser;
-use std::error::Error;
+use clap::{Parser, Subcommand};
+use libp2p::PeerId;
+use libp2p_workshop_node::chat_log::verify_history;
+use libp2p_workshop_node::export::{export_chat, Format};
+use libp2p_workshop_node::storage::FsStorage;
+use libp2p_workshop_node::store::Store;
+use libp2p_workshop_node::NodeError;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
 
 #[derive(Debug, Parser)]
 #[clap(name = "libp2p-workshop-node")]
-struct Opts {}
+struct Opts {
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Remove stale partial downloads and enforce a download directory quota.
+    Gc {
+        /// Directory holding the downloads.
+        #[clap(long)]
+        download_dir: PathBuf,
+        /// Remove partial downloads not modified for this many seconds.
+        #[clap(long, default_value_t = 24 * 60 * 60)]
+        partial_ttl_secs: u64,
+        /// Delete the oldest completed downloads until the rest fit into
+        /// this many bytes.
+        #[clap(long)]
+        quota: Option<u64>,
+    },
+    /// Check that the stored chat messages of a peer form an unbroken hash
+    /// chain, reporting gaps and forks.
+    VerifyHistory {
+        /// Database holding the chat log.
+        #[clap(long)]
+        db: PathBuf,
+        peer_id: PeerId,
+    },
+    /// Write a transcript of a topic's chat history, with authors named by
+    /// their nicknames.
+    ExportChat {
+        /// Database holding the chat log.
+        #[clap(long)]
+        db: PathBuf,
+        #[clap(long, default_value = "chat")]
+        topic: String,
+        /// One of md, html or json.
+        #[clap(long, default_value = "md")]
+        format: Format,
+        path: PathBuf,
+    },
+}
 
 #[async_std::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> ExitCode {
     env_logger::init();
-    let _opts = Opts::parse();
+    let opts = Opts::parse();
+
+    match run(opts).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {}", describe(&e));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(opts: Opts) -> Result<ExitCode, NodeError> {
+    match opts.command {
+        None => println!("Hello, world!"),
+        Some(Command::Gc {
+            download_dir,
+            partial_ttl_secs,
+            quota,
+        }) => {
+            let reclaimed = FsStorage::new(download_dir)
+                .collect_garbage(Duration::from_secs(partial_ttl_secs), quota)
+                .await?;
+            println!("Reclaimed {reclaimed} bytes.");
+        }
+        Some(Command::VerifyHistory { db, peer_id }) => {
+            let messages = Store::open_existing(db)?.chat_by_author(&peer_id)?;
+            let report = verify_history(&peer_id, &messages);
 
-    println!("Hello, world!");
+            println!("Checked {} messages by {peer_id}.", report.messages);
+            for gap in &report.gaps {
+                println!(
+                    "Gap: messages {} to {} are missing.",
+                    gap.start(),
+                    gap.end()
+                );
+            }
+            for seq in &report.forks {
+                println!("Fork: message {seq} exists in several versions.");
+            }
+            for seq in &report.broken_links {
+                println!("Broken link: message {seq} does not follow its predecessor.");
+            }
+            if !report.is_intact() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Some(Command::ExportChat {
+            db,
+            topic,
+            format,
+            path,
+        }) => {
+            let transcript = export_chat(&Store::open_existing(db)?, &topic, format)?;
+            std::fs::write(&path, transcript)?;
+            println!("Wrote the transcript of {topic} to {}.", path.display());
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
 
-    Ok(())
+/// Turns a library error into a message telling the user what to do about it.
+fn describe(error: &NodeError) -> String {
+    match error {
+        NodeError::Io(e) if e.kind() == io::ErrorKind::NotFound => {
+            format!("{error}. Check that the path exists.")
+        }
+        NodeError::Io(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            format!("{error}. Check the permissions of the path.")
+        }
+        NodeError::HashMismatch { .. } => {
+            format!("{error}. The download was discarded, please fetch it again.")
+        }
+        NodeError::ReservedName(_) | NodeError::PathOutsideSandbox(_) => {
+            format!("{error}. Choose a plain file name inside the directory.")
+        }
+        _ => error.to_string(),
+    }
 }
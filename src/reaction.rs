@@ -0,0 +1,199 @@
+use libp2p::PeerId;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::str::FromStr;
+
+use crate::chat_log::MessageId;
+use crate::wire::Reaction;
+use crate::NodeError;
+
+/// Longest emoji accepted in a reaction, in bytes. Enough for flags and
+/// emoji sequences joined with zero-width joiners.
+const MAX_EMOJI_LEN: usize = 32;
+
+/// Returns the reaction of `reactor` with `emoji` to the message `target`.
+pub fn react(target: MessageId, emoji: &str, reactor: PeerId) -> Reaction {
+    Reaction {
+        target: Some(target.into()),
+        emoji: emoji.to_string(),
+        reactor: reactor.to_bytes(),
+    }
+}
+
+fn check_emoji(emoji: &str) -> Result<(), String> {
+    if emoji.is_empty() || emoji.len() > MAX_EMOJI_LEN || emoji.contains(char::is_whitespace) {
+        return Err(format!(
+            "invalid emoji {emoji:?}, expected up to {MAX_EMOJI_LEN} bytes without whitespace"
+        ));
+    }
+    Ok(())
+}
+
+/// Reaction counts per message, derived from the reactions received on a
+/// topic.
+///
+/// A peer reacting with the same emoji more than once is counted once, so
+/// reactions may be received in any order and any number of times.
+#[derive(Debug, Default, Clone)]
+pub struct Reactions {
+    by_message: HashMap<MessageId, BTreeMap<String, BTreeSet<PeerId>>>,
+}
+
+impl Reactions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a received reaction. Returns whether it changed any count.
+    pub fn apply(&mut self, reaction: &Reaction) -> Result<bool, NodeError> {
+        let target = reaction
+            .target
+            .as_ref()
+            .ok_or_else(|| NodeError::Protocol("reaction without target".to_string()))?;
+        let target = MessageId::try_from(target)?;
+        let reactor = PeerId::from_bytes(&reaction.reactor)
+            .map_err(|e| NodeError::Protocol(format!("invalid reactor: {e}")))?;
+        check_emoji(&reaction.emoji).map_err(NodeError::Protocol)?;
+
+        Ok(self
+            .by_message
+            .entry(target)
+            .or_default()
+            .entry(reaction.emoji.clone())
+            .or_default()
+            .insert(reactor))
+    }
+
+    /// Returns how often each emoji was used on `message`, ordered by emoji.
+    pub fn counts(&self, message: &MessageId) -> Vec<(&str, usize)> {
+        self.by_message
+            .get(message)
+            .into_iter()
+            .flatten()
+            .map(|(emoji, reactors)| (emoji.as_str(), reactors.len()))
+            .collect()
+    }
+
+    /// Formats the counts of `message` for display after it, as in
+    /// `[👍 2] [🎉 1]`. Empty if nobody reacted.
+    pub fn summary(&self, message: &MessageId) -> String {
+        self.counts(message)
+            .into_iter()
+            .map(|(emoji, count)| format!("[{emoji} {count}]"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// `REACT <msg_id> <emoji>`, reacting to the message `<peer-id>/<seq>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReactCommand {
+    pub target: MessageId,
+    pub emoji: String,
+}
+
+impl FromStr for ReactCommand {
+    type Err = NodeError;
+
+    fn from_str(line: &str) -> Result<Self, NodeError> {
+        let invalid = || NodeError::Command(line.to_string());
+        let mut words = line.split_whitespace();
+        if !words
+            .next()
+            .is_some_and(|command| command.eq_ignore_ascii_case("REACT"))
+        {
+            return Err(invalid());
+        }
+        let (Some(target), Some(emoji), None) = (words.next(), words.next(), words.next()) else {
+            return Err(invalid());
+        };
+        check_emoji(emoji).map_err(NodeError::Command)?;
+        Ok(ReactCommand {
+            target: target.parse()?,
+            emoji: emoji.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> MessageId {
+        MessageId {
+            author: PeerId::random(),
+            seq: 3,
+        }
+    }
+
+    #[test]
+    fn counts_each_reactor_once() {
+        let (target, other) = (message(), message());
+        let (alice, bob) = (PeerId::random(), PeerId::random());
+        let mut reactions = Reactions::new();
+
+        assert!(reactions.apply(&react(target, "👍", alice)).unwrap());
+        assert!(reactions.apply(&react(target, "👍", bob)).unwrap());
+        assert!(!reactions.apply(&react(target, "👍", alice)).unwrap());
+        assert!(reactions.apply(&react(target, "🎉", alice)).unwrap());
+        assert!(reactions.apply(&react(other, "👍", bob)).unwrap());
+
+        assert_eq!(reactions.counts(&target), vec![("🎉", 1), ("👍", 2)]);
+        assert_eq!(reactions.summary(&target), "[🎉 1] [👍 2]");
+        assert_eq!(reactions.summary(&message()), "");
+    }
+
+    #[test]
+    fn rejects_malformed_reactions() {
+        let mut reactions = Reactions::new();
+        let valid = react(message(), "👍", PeerId::random());
+        for invalid in [
+            Reaction {
+                target: None,
+                ..valid.clone()
+            },
+            Reaction {
+                reactor: vec![1, 2, 3],
+                ..valid.clone()
+            },
+            Reaction {
+                emoji: "".into(),
+                ..valid.clone()
+            },
+            Reaction {
+                emoji: "👍".repeat(9),
+                ..valid
+            },
+        ] {
+            assert!(
+                matches!(reactions.apply(&invalid), Err(NodeError::Protocol(_))),
+                "{invalid:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_commands() {
+        let target = message();
+        assert_eq!(
+            format!("react {target}  👍")
+                .parse::<ReactCommand>()
+                .unwrap(),
+            ReactCommand {
+                target,
+                emoji: "👍".into()
+            }
+        );
+        for invalid in [
+            "REACT".to_string(),
+            format!("REACT {target}"),
+            format!("REACT {target} 👍 🎉"),
+            format!("SAY {target} 👍"),
+            "REACT nobody/1 👍".to_string(),
+        ] {
+            assert!(
+                matches!(invalid.parse::<ReactCommand>(), Err(NodeError::Command(_))),
+                "{invalid:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use async_std::fs::{self, File, OpenOptions};
+use async_std::path::{Component, Path, PathBuf};
+use std::io;
+
+use crate::NodeError;
+
+/// Confines file paths to a root directory.
+///
+/// Paths are resolved relative to the root and rejected if they, or any
+/// symlink along them, lead outside of it.
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    root: PathBuf,
+}
+
+impl Sandbox {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Sandbox { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolves `path`, which must already exist, to its canonical form
+    /// inside the root.
+    pub async fn resolve_existing(&self, path: impl AsRef<Path>) -> Result<PathBuf, NodeError> {
+        let path = path.as_ref();
+        if path.as_os_str().is_empty() {
+            return Err(outside(path));
+        }
+        let root = fs::canonicalize(&self.root).await?;
+        let resolved = fs::canonicalize(self.root.join(path)).await?;
+        if !resolved.starts_with(&root) {
+            return Err(outside(path));
+        }
+        Ok(resolved)
+    }
+
+    /// Resolves `path`, which may not exist yet, for writing inside the root.
+    ///
+    /// `path` must be relative and made of plain file names only, and none of
+    /// its existing ancestors may be a symlink pointing outside of the root.
+    pub async fn resolve_new(&self, path: impl AsRef<Path>) -> Result<PathBuf, NodeError> {
+        let path = path.as_ref();
+        let plain = path.components().all(|c| matches!(c, Component::Normal(_)));
+        if !plain || path.as_os_str().is_empty() {
+            return Err(outside(path));
+        }
+
+        let root = fs::canonicalize(&self.root).await?;
+        let joined = root.join(path);
+
+        // Canonicalize the longest existing prefix, following any symlinks.
+        let mut existing = joined.as_path();
+        loop {
+            match fs::symlink_metadata(existing).await {
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    existing = existing.parent().unwrap_or(&root);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let inside = match fs::canonicalize(existing).await {
+            Ok(resolved) => resolved.starts_with(&root),
+            // A dangling symlink, which writing would follow to wherever it points.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e.into()),
+        };
+        if !inside {
+            return Err(outside(path));
+        }
+
+        Ok(joined)
+    }
+}
+
+/// Opens `path`, as resolved by a [`Sandbox`], without following a symlink
+/// in its last component.
+///
+/// A symlink planted between resolving and opening `path` then fails the
+/// open instead of redirecting it outside of the root. Symlinked parent
+/// directories are only checked while resolving.
+pub async fn open_no_follow(path: &Path, options: &mut OpenOptions) -> io::Result<File> {
+    #[cfg(unix)]
+    {
+        use async_std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_NOFOLLOW);
+    }
+    options.open(path).await
+}
+
+fn outside(path: &Path) -> NodeError {
+    NodeError::PathOutsideSandbox(path.to_path_buf().into())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    struct Fixture {
+        // Keeps the directories alive for the duration of the test.
+        _root: tempfile::TempDir,
+        outside: tempfile::TempDir,
+        sandbox: Sandbox,
+    }
+
+    fn fixture() -> Fixture {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("sub")).unwrap();
+        std::fs::write(root.path().join("sub/file"), b"inside").unwrap();
+        std::fs::write(outside.path().join("secret"), b"outside").unwrap();
+        symlink(outside.path(), root.path().join("escape")).unwrap();
+        symlink(
+            outside.path().join("secret"),
+            root.path().join("name.partial"),
+        )
+        .unwrap();
+        symlink(
+            outside.path().join("missing"),
+            root.path().join("dangling.partial"),
+        )
+        .unwrap();
+
+        let sandbox = Sandbox::new(root.path());
+        Fixture {
+            _root: root,
+            outside,
+            sandbox,
+        }
+    }
+
+    fn is_outside<T: std::fmt::Debug>(result: Result<T, NodeError>) -> bool {
+        matches!(result, Err(NodeError::PathOutsideSandbox(_)))
+    }
+
+    #[async_std::test]
+    async fn resolve_new_rejects_escapes() {
+        let f = fixture();
+        let absolute = f.outside.path().join("new");
+
+        assert!(is_outside(f.sandbox.resolve_new("..").await));
+        assert!(is_outside(f.sandbox.resolve_new("../new").await));
+        assert!(is_outside(f.sandbox.resolve_new("sub/../../new").await));
+        assert!(is_outside(f.sandbox.resolve_new(&absolute).await));
+        assert!(is_outside(f.sandbox.resolve_new("").await));
+        assert!(is_outside(f.sandbox.resolve_new("escape/new").await));
+        assert!(is_outside(f.sandbox.resolve_new("name.partial").await));
+        assert!(is_outside(f.sandbox.resolve_new("dangling.partial").await));
+    }
+
+    #[async_std::test]
+    async fn resolve_new_accepts_plain_names() {
+        let f = fixture();
+        let root = fs::canonicalize(f.sandbox.root()).await.unwrap();
+
+        assert_eq!(
+            f.sandbox.resolve_new("new").await.unwrap(),
+            root.join("new")
+        );
+        assert_eq!(
+            f.sandbox.resolve_new("sub/new").await.unwrap(),
+            root.join("sub/new")
+        );
+        assert_eq!(
+            f.sandbox.resolve_new("sub/missing/new").await.unwrap(),
+            root.join("sub/missing/new")
+        );
+    }
+
+    #[async_std::test]
+    async fn open_no_follow_rejects_symlinks_planted_after_resolving() {
+        let f = fixture();
+        let path = f.sandbox.resolve_new("late").await.unwrap();
+        symlink(f.outside.path().join("secret"), &path).unwrap();
+
+        let result = open_no_follow(&path, OpenOptions::new().write(true).truncate(true)).await;
+        assert!(result.is_err());
+        assert_eq!(
+            std::fs::read(f.outside.path().join("secret")).unwrap(),
+            b"outside"
+        );
+
+        let path = f.sandbox.resolve_new("new").await.unwrap();
+        open_no_follow(&path, OpenOptions::new().create(true).write(true))
+            .await
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn resolve_existing_rejects_escapes() {
+        let f = fixture();
+        let absolute = f.outside.path().join("secret");
+
+        assert!(is_outside(f.sandbox.resolve_existing("..").await));
+        assert!(is_outside(f.sandbox.resolve_existing(&absolute).await));
+        assert!(is_outside(f.sandbox.resolve_existing("").await));
+        assert!(is_outside(
+            f.sandbox.resolve_existing("escape/secret").await
+        ));
+        assert!(is_outside(f.sandbox.resolve_existing("name.partial").await));
+    }
+
+    #[async_std::test]
+    async fn resolve_existing_accepts_files_inside() {
+        let f = fixture();
+        let root = fs::canonicalize(f.sandbox.root()).await.unwrap();
+
+        assert_eq!(
+            f.sandbox.resolve_existing("sub/file").await.unwrap(),
+            root.join("sub/file")
+        );
+        assert_eq!(
+            f.sandbox.resolve_existing("sub/../sub/file").await.unwrap(),
+            root.join("sub/file")
+        );
+    }
+}
@@ -0,0 +1,776 @@
+use async_std::fs::{self, File, OpenOptions};
+use async_std::io::prelude::*;
+use async_std::io::SeekFrom;
+use async_std::path::{Path, PathBuf};
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+use async_trait::async_trait;
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io;
+use std::time::{Duration, SystemTime};
+
+use crate::hash::sha256_file;
+use crate::sandbox::{open_no_follow, Sandbox};
+use crate::NodeError;
+
+/// Destination for downloaded files.
+///
+/// A download is opened with [`Storage::open_for_write`], filled with
+/// [`Storage::write_chunk`] and made visible to [`Storage::read_range`] by
+/// [`Storage::finalize`].
+#[async_trait]
+pub trait Storage: Send {
+    /// Prepares `name` to receive a new download, discarding any previous
+    /// unfinished download of the same name.
+    async fn open_for_write(&mut self, name: &str) -> Result<(), NodeError>;
+
+    /// Writes `data` at `offset` of the unfinished download `name`.
+    async fn write_chunk(&mut self, name: &str, offset: u64, data: &[u8]) -> Result<(), NodeError>;
+
+    /// Completes the download `name`.
+    ///
+    /// If `expected_sha256` is given, the assembled content is checked
+    /// against it first and the download is discarded on mismatch.
+    async fn finalize(
+        &mut self,
+        name: &str,
+        expected_sha256: Option<[u8; 32]>,
+    ) -> Result<(), NodeError>;
+
+    /// Reads up to `len` bytes, but no more than [`MAX_CHUNK_LEN`], of the
+    /// completed file `name`, starting at `offset`.
+    async fn read_range(
+        &mut self,
+        name: &str,
+        offset: u64,
+        len: usize,
+    ) -> Result<Vec<u8>, NodeError>;
+}
+
+/// Most bytes [`Storage::read_range`] returns at once, whatever length a
+/// peer asks for.
+pub const MAX_CHUNK_LEN: usize = 1024 * 1024;
+
+/// When [`FsStorage`] flushes written chunks to disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Sync after every chunk.
+    EveryChunk,
+    /// Sync whenever at least this many bytes were written since the last sync.
+    EveryBytes(u64),
+    /// Sync only when the download is finalized.
+    #[default]
+    OnFinalize,
+}
+
+impl SyncPolicy {
+    fn should_sync(&self, unsynced: u64) -> bool {
+        match self {
+            SyncPolicy::EveryChunk => true,
+            SyncPolicy::EveryBytes(threshold) => unsynced >= *threshold,
+            SyncPolicy::OnFinalize => false,
+        }
+    }
+}
+
+/// Stores downloads in a directory on the local filesystem.
+///
+/// Unfinished downloads are written to `<name>.partial` and atomically
+/// renamed to `<name>` once finalized, so a crash never leaves a truncated
+/// or unverified file under the final name. Names ending in `.partial` are
+/// therefore rejected, and all names are confined to the storage directory
+/// by a [`Sandbox`].
+pub struct FsStorage {
+    sandbox: Sandbox,
+    sync_policy: SyncPolicy,
+    max_file_size: u64,
+    open: HashMap<String, PartialFile>,
+}
+
+struct PartialFile {
+    file: File,
+    unsynced: u64,
+}
+
+impl FsStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FsStorage {
+            sandbox: Sandbox::new(root),
+            sync_policy: SyncPolicy::default(),
+            max_file_size: DEFAULT_MAX_FS_FILE_SIZE,
+            open: HashMap::new(),
+        }
+    }
+
+    pub fn with_sync_policy(mut self, sync_policy: SyncPolicy) -> Self {
+        self.sync_policy = sync_policy;
+        self
+    }
+
+    /// Rejects chunks that would grow a download beyond `max_file_size` bytes.
+    pub fn with_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    async fn partial_path(&self, name: &str) -> Result<PathBuf, NodeError> {
+        check_name(name)?;
+        self.sandbox
+            .resolve_new(format!("{name}{PARTIAL_SUFFIX}"))
+            .await
+    }
+
+    async fn final_path(&self, name: &str) -> Result<PathBuf, NodeError> {
+        check_name(name)?;
+        self.sandbox.resolve_new(name).await
+    }
+
+    /// Removes `.partial` files not modified within `partial_ttl` and, if
+    /// `quota` is set, deletes the oldest completed files until the rest fit
+    /// into `quota` bytes. Downloads currently open for writing are left alone.
+    ///
+    /// Returns the number of bytes reclaimed.
+    pub async fn collect_garbage(
+        &self,
+        partial_ttl: Duration,
+        quota: Option<u64>,
+    ) -> Result<u64, NodeError> {
+        let garbage = find_garbage(self.sandbox.root(), partial_ttl, quota).await?;
+        self.remove_garbage(garbage).await
+    }
+
+    /// Deletes `garbage`, except for downloads opened since it was found.
+    async fn remove_garbage(&self, garbage: Garbage) -> Result<u64, NodeError> {
+        let partial = garbage
+            .partial
+            .into_iter()
+            .filter(|(name, _, _)| !self.open.contains_key(name))
+            .map(|(_, path, len)| (path, len));
+        let mut reclaimed = 0;
+        for (path, len) in partial.chain(garbage.complete) {
+            match fs::remove_file(&path).await {
+                Ok(()) => reclaimed += len,
+                // Finalized or removed since the walk.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(reclaimed)
+    }
+}
+
+/// Files found by [`find_garbage`].
+struct Garbage {
+    /// Stale `.partial` files, with the name of their download and length.
+    partial: Vec<(String, PathBuf, u64)>,
+    /// Completed files exceeding the quota, with their length.
+    complete: Vec<(PathBuf, u64)>,
+}
+
+/// Walks `root` for the files [`FsStorage::collect_garbage`] deletes.
+///
+/// The walk does not need the storage itself, so a shared storage stays
+/// available to running downloads while it takes place.
+async fn find_garbage(
+    root: &Path,
+    partial_ttl: Duration,
+    quota: Option<u64>,
+) -> Result<Garbage, NodeError> {
+    let now = SystemTime::now();
+    let mut partial = Vec::new();
+    let mut complete = Vec::new();
+
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            let path = entry.path();
+            let metadata = fs::symlink_metadata(&path).await?;
+            if metadata.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            if !metadata.is_file() {
+                continue;
+            }
+            let modified = metadata.modified()?;
+            let name = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            match name.strip_suffix(PARTIAL_SUFFIX) {
+                Some(name) => {
+                    let age = now.duration_since(modified).unwrap_or_default();
+                    if age > partial_ttl {
+                        partial.push((name.to_string(), path, metadata.len()));
+                    }
+                }
+                None => complete.push((modified, metadata.len(), path)),
+            }
+        }
+    }
+
+    let mut over_quota = Vec::new();
+    if let Some(quota) = quota {
+        let mut used: u64 = complete.iter().map(|(_, len, _)| len).sum();
+        complete.sort_by_key(|(modified, _, _)| *modified);
+        for (_, len, path) in complete {
+            if used <= quota {
+                break;
+            }
+            used -= len;
+            over_quota.push((path, len));
+        }
+    }
+
+    Ok(Garbage {
+        partial,
+        complete: over_quota,
+    })
+}
+
+/// Runs [`FsStorage::collect_garbage`] every `interval`, logging how much
+/// space each run reclaimed.
+///
+/// `storage` is only locked to delete what a walk of its directory found,
+/// not during the walk itself.
+pub async fn collect_garbage_periodically(
+    storage: Arc<Mutex<FsStorage>>,
+    interval: Duration,
+    partial_ttl: Duration,
+    quota: Option<u64>,
+) {
+    let root = storage.lock().await.sandbox.root().to_path_buf();
+    loop {
+        task::sleep(interval).await;
+        let reclaimed = match find_garbage(&root, partial_ttl, quota).await {
+            Ok(garbage) => storage.lock().await.remove_garbage(garbage).await,
+            Err(e) => Err(e),
+        };
+        match reclaimed {
+            Ok(0) => {}
+            Ok(reclaimed) => log::info!("Garbage collection reclaimed {reclaimed} bytes"),
+            Err(e) => log::warn!("Garbage collection failed: {e}"),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for FsStorage {
+    async fn open_for_write(&mut self, name: &str) -> Result<(), NodeError> {
+        fs::create_dir_all(self.sandbox.root()).await?;
+        let file = open_no_follow(
+            &self.partial_path(name).await?,
+            OpenOptions::new().create(true).write(true).truncate(true),
+        )
+        .await?;
+        self.open
+            .insert(name.to_string(), PartialFile { file, unsynced: 0 });
+        Ok(())
+    }
+
+    async fn write_chunk(&mut self, name: &str, offset: u64, data: &[u8]) -> Result<(), NodeError> {
+        let partial = self
+            .open
+            .get_mut(name)
+            .ok_or_else(|| NodeError::NotOpen(name.to_string()))?;
+        chunk_end(name, offset, data, self.max_file_size)?;
+        partial.file.seek(SeekFrom::Start(offset)).await?;
+        partial.file.write_all(data).await?;
+        partial.unsynced += data.len() as u64;
+
+        if self.sync_policy.should_sync(partial.unsynced) {
+            partial.file.sync_data().await?;
+            partial.unsynced = 0;
+        }
+        Ok(())
+    }
+
+    async fn finalize(
+        &mut self,
+        name: &str,
+        expected_sha256: Option<[u8; 32]>,
+    ) -> Result<(), NodeError> {
+        // The download stays registered until it is in place, so that a
+        // failed attempt can be retried.
+        let partial = self
+            .open
+            .get_mut(name)
+            .ok_or_else(|| NodeError::NotOpen(name.to_string()))?;
+        partial.file.flush().await?;
+        partial.file.sync_all().await?;
+        partial.unsynced = 0;
+
+        let partial_path = self.partial_path(name).await?;
+        let final_path = self.final_path(name).await?;
+        if let Some(expected) = expected_sha256 {
+            if sha256_file(&partial_path, |_| {}).await? != expected {
+                self.open.remove(name);
+                fs::remove_file(&partial_path).await?;
+                return Err(NodeError::HashMismatch {
+                    name: name.to_string(),
+                });
+            }
+        }
+
+        // Close the file before renaming it, reopening it if that fails.
+        self.open.remove(name);
+        if let Err(e) = fs::rename(&partial_path, &final_path).await {
+            let file = open_no_follow(&partial_path, OpenOptions::new().write(true)).await?;
+            self.open
+                .insert(name.to_string(), PartialFile { file, unsynced: 0 });
+            return Err(e.into());
+        }
+        // Persist the rename itself.
+        #[cfg(unix)]
+        if let Some(dir) = final_path.parent() {
+            File::open(dir).await?.sync_all().await?;
+        }
+        Ok(())
+    }
+
+    async fn read_range(
+        &mut self,
+        name: &str,
+        offset: u64,
+        len: usize,
+    ) -> Result<Vec<u8>, NodeError> {
+        check_name(name)?;
+        let path = self.sandbox.resolve_existing(name).await?;
+        let mut file = open_no_follow(&path, OpenOptions::new().read(true)).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut buf = Vec::new();
+        file.take(len.min(MAX_CHUNK_LEN) as u64)
+            .read_to_end(&mut buf)
+            .await?;
+        Ok(buf)
+    }
+}
+
+/// Largest file [`FsStorage`] accepts unless configured otherwise.
+pub const DEFAULT_MAX_FS_FILE_SIZE: u64 = 64 * 1024 * 1024 * 1024;
+
+/// Largest file [`MemoryStorage`] accepts unless configured otherwise.
+pub const DEFAULT_MAX_MEMORY_FILE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Keeps downloads in memory, e.g. for embedders forwarding them elsewhere.
+pub struct MemoryStorage {
+    max_file_size: u64,
+    partial: HashMap<String, Vec<u8>>,
+    complete: HashMap<String, Vec<u8>>,
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        MemoryStorage {
+            max_file_size: DEFAULT_MAX_MEMORY_FILE_SIZE,
+            partial: HashMap::new(),
+            complete: HashMap::new(),
+        }
+    }
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rejects chunks that would grow a download beyond `max_file_size` bytes.
+    pub fn with_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// Returns the content of the completed file `name`.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.complete.get(name).map(Vec::as_slice)
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn open_for_write(&mut self, name: &str) -> Result<(), NodeError> {
+        self.partial.insert(name.to_string(), Vec::new());
+        Ok(())
+    }
+
+    async fn write_chunk(&mut self, name: &str, offset: u64, data: &[u8]) -> Result<(), NodeError> {
+        let buf = self
+            .partial
+            .get_mut(name)
+            .ok_or_else(|| NodeError::NotOpen(name.to_string()))?;
+        let end = chunk_end(name, offset, data, self.max_file_size)?;
+        let too_large = || NodeError::FileTooLarge {
+            name: name.to_string(),
+        };
+        let start = usize::try_from(offset).map_err(|_| too_large())?;
+        let end = usize::try_from(end).map_err(|_| too_large())?;
+
+        if buf.len() < end {
+            buf.resize(end, 0);
+        }
+        buf[start..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    async fn finalize(
+        &mut self,
+        name: &str,
+        expected_sha256: Option<[u8; 32]>,
+    ) -> Result<(), NodeError> {
+        let buf = self
+            .partial
+            .remove(name)
+            .ok_or_else(|| NodeError::NotOpen(name.to_string()))?;
+        if let Some(expected) = expected_sha256 {
+            if Sha256::digest(&buf).as_slice() != expected {
+                return Err(NodeError::HashMismatch {
+                    name: name.to_string(),
+                });
+            }
+        }
+        self.complete.insert(name.to_string(), buf);
+        Ok(())
+    }
+
+    async fn read_range(
+        &mut self,
+        name: &str,
+        offset: u64,
+        len: usize,
+    ) -> Result<Vec<u8>, NodeError> {
+        let buf = self
+            .complete
+            .get(name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, name.to_string()))?;
+        let start = usize::try_from(offset).unwrap_or(usize::MAX).min(buf.len());
+        let end = start.saturating_add(len.min(MAX_CHUNK_LEN)).min(buf.len());
+        Ok(buf[start..end].to_vec())
+    }
+}
+
+/// Suffix of unfinished downloads, which announced names may not end in.
+pub(crate) const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Returns where `data` written at `offset` of download `name` ends, if that
+/// is within `max_file_size`.
+fn chunk_end(name: &str, offset: u64, data: &[u8], max_file_size: u64) -> Result<u64, NodeError> {
+    offset
+        .checked_add(data.len() as u64)
+        .filter(|end| *end <= max_file_size)
+        .ok_or_else(|| NodeError::FileTooLarge {
+            name: name.to_string(),
+        })
+}
+
+fn check_name(name: &str) -> Result<(), NodeError> {
+    if name.ends_with(PARTIAL_SUFFIX) {
+        return Err(NodeError::ReservedName(name.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn memory_storage_assembles_out_of_order_chunks() {
+        let mut storage = MemoryStorage::new();
+        storage.open_for_write("a").await.unwrap();
+        storage.write_chunk("a", 5, b"world").await.unwrap();
+        storage.write_chunk("a", 0, b"hello").await.unwrap();
+        storage.finalize("a", None).await.unwrap();
+
+        assert_eq!(storage.get("a"), Some(&b"helloworld"[..]));
+        assert_eq!(storage.read_range("a", 3, 4).await.unwrap(), b"lowo");
+        assert_eq!(storage.read_range("a", 8, usize::MAX).await.unwrap(), b"ld");
+        assert!(storage
+            .read_range("a", u64::MAX, 1)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[async_std::test]
+    async fn memory_storage_rejects_chunks_beyond_max_file_size() {
+        let mut storage = MemoryStorage::new().with_max_file_size(8);
+        storage.open_for_write("a").await.unwrap();
+
+        for offset in [4, u64::MAX - 1, u64::MAX] {
+            assert!(matches!(
+                storage.write_chunk("a", offset, b"hello").await,
+                Err(NodeError::FileTooLarge { .. })
+            ));
+        }
+        storage.write_chunk("a", 3, b"hello").await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn memory_storage_requires_open_download() {
+        let mut storage = MemoryStorage::new();
+        assert!(storage.write_chunk("a", 0, b"x").await.is_err());
+        assert!(storage.finalize("a", None).await.is_err());
+        assert!(storage.read_range("a", 0, 1).await.is_err());
+    }
+
+    #[async_std::test]
+    async fn fs_storage_assembles_out_of_order_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = FsStorage::new(dir.path());
+        storage.open_for_write("a").await.unwrap();
+        storage.write_chunk("a", 5, b"world").await.unwrap();
+        storage.write_chunk("a", 0, b"hello").await.unwrap();
+
+        assert!(dir.path().join("a.partial").exists());
+        assert!(storage.read_range("a", 0, 1).await.is_err());
+
+        storage.finalize("a", None).await.unwrap();
+
+        assert!(!dir.path().join("a.partial").exists());
+        assert_eq!(std::fs::read(dir.path().join("a")).unwrap(), b"helloworld");
+        assert_eq!(storage.read_range("a", 3, 4).await.unwrap(), b"lowo");
+        assert_eq!(storage.read_range("a", 8, usize::MAX).await.unwrap(), b"ld");
+    }
+
+    #[async_std::test]
+    async fn fs_storage_rejects_chunks_beyond_max_file_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = FsStorage::new(dir.path()).with_max_file_size(8);
+        storage.open_for_write("a").await.unwrap();
+
+        for offset in [4, u64::MAX - 1, u64::MAX] {
+            assert!(matches!(
+                storage.write_chunk("a", offset, b"hello").await,
+                Err(NodeError::FileTooLarge { .. })
+            ));
+        }
+        storage.write_chunk("a", 3, b"hello").await.unwrap();
+        storage.finalize("a", None).await.unwrap();
+        assert_eq!(std::fs::metadata(dir.path().join("a")).unwrap().len(), 8);
+    }
+
+    #[async_std::test]
+    async fn read_range_is_capped_at_max_chunk_len() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = vec![7; MAX_CHUNK_LEN + 10];
+        let mut fs = FsStorage::new(dir.path());
+        let mut memory = MemoryStorage::new();
+        let storages: [&mut dyn Storage; 2] = [&mut fs, &mut memory];
+
+        for storage in storages {
+            storage.open_for_write("big").await.unwrap();
+            storage.write_chunk("big", 0, &content).await.unwrap();
+            storage.finalize("big", None).await.unwrap();
+
+            let read = storage.read_range("big", 0, usize::MAX).await.unwrap();
+            assert_eq!(read.len(), MAX_CHUNK_LEN);
+            let rest = storage.read_range("big", MAX_CHUNK_LEN as u64, 100).await;
+            assert_eq!(rest.unwrap().len(), 10);
+        }
+    }
+
+    #[test]
+    fn sync_policy_thresholds() {
+        assert!(SyncPolicy::EveryChunk.should_sync(0));
+        assert!(!SyncPolicy::EveryBytes(10).should_sync(9));
+        assert!(SyncPolicy::EveryBytes(10).should_sync(10));
+        assert!(!SyncPolicy::OnFinalize.should_sync(u64::MAX));
+    }
+
+    #[async_std::test]
+    async fn fs_storage_applies_sync_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let unsynced = |storage: &FsStorage| storage.open["a"].unsynced;
+
+        let mut storage = FsStorage::new(dir.path()).with_sync_policy(SyncPolicy::EveryChunk);
+        storage.open_for_write("a").await.unwrap();
+        storage.write_chunk("a", 0, b"1234").await.unwrap();
+        assert_eq!(unsynced(&storage), 0);
+
+        let mut storage = FsStorage::new(dir.path()).with_sync_policy(SyncPolicy::EveryBytes(10));
+        storage.open_for_write("a").await.unwrap();
+        storage.write_chunk("a", 0, b"1234").await.unwrap();
+        storage.write_chunk("a", 4, b"1234").await.unwrap();
+        assert_eq!(unsynced(&storage), 8);
+        storage.write_chunk("a", 8, b"1234").await.unwrap();
+        assert_eq!(unsynced(&storage), 0);
+
+        let mut storage = FsStorage::new(dir.path());
+        storage.open_for_write("a").await.unwrap();
+        storage.write_chunk("a", 0, &[0; 4096]).await.unwrap();
+        assert_eq!(unsynced(&storage), 4096);
+        storage.finalize("a", None).await.unwrap();
+        assert!(storage.open.is_empty());
+    }
+
+    #[async_std::test]
+    async fn fs_storage_discards_download_on_hash_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let expected: [u8; 32] = Sha256::digest(b"hello").into();
+        let mut storage = FsStorage::new(dir.path());
+
+        storage.open_for_write("bad").await.unwrap();
+        storage.write_chunk("bad", 0, b"hellx").await.unwrap();
+        assert!(matches!(
+            storage.finalize("bad", Some(expected)).await,
+            Err(NodeError::HashMismatch { .. })
+        ));
+        assert!(!dir.path().join("bad.partial").exists());
+        assert!(!dir.path().join("bad").exists());
+        assert!(matches!(
+            storage.finalize("bad", Some(expected)).await,
+            Err(NodeError::NotOpen(_))
+        ));
+
+        storage.open_for_write("good").await.unwrap();
+        storage.write_chunk("good", 0, b"hello").await.unwrap();
+        storage.finalize("good", Some(expected)).await.unwrap();
+        assert_eq!(std::fs::read(dir.path().join("good")).unwrap(), b"hello");
+    }
+
+    #[async_std::test]
+    async fn fs_storage_finalize_can_be_retried_after_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = FsStorage::new(dir.path());
+        storage.open_for_write("a").await.unwrap();
+        storage.write_chunk("a", 0, b"hello").await.unwrap();
+
+        // A non-empty directory in the way makes the rename fail.
+        std::fs::create_dir_all(dir.path().join("a/blocker")).unwrap();
+        assert!(matches!(
+            storage.finalize("a", None).await,
+            Err(NodeError::Io(_))
+        ));
+        assert!(dir.path().join("a.partial").exists());
+
+        std::fs::remove_dir_all(dir.path().join("a")).unwrap();
+        storage.write_chunk("a", 5, b"!").await.unwrap();
+        storage.finalize("a", None).await.unwrap();
+        assert_eq!(std::fs::read(dir.path().join("a")).unwrap(), b"hello!");
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn fs_storage_does_not_follow_partial_symlinks_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret"), b"outside").unwrap();
+        std::os::unix::fs::symlink(outside.path().join("secret"), dir.path().join("a.partial"))
+            .unwrap();
+
+        let mut storage = FsStorage::new(dir.path());
+        assert!(matches!(
+            storage.open_for_write("a").await,
+            Err(NodeError::PathOutsideSandbox(_))
+        ));
+        assert_eq!(
+            std::fs::read(outside.path().join("secret")).unwrap(),
+            b"outside"
+        );
+    }
+
+    #[async_std::test]
+    async fn fs_storage_rejects_partial_names() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.partial"), b"unfinished").unwrap();
+        let mut storage = FsStorage::new(dir.path());
+
+        assert!(matches!(
+            storage.open_for_write("report.partial").await,
+            Err(NodeError::ReservedName(_))
+        ));
+        assert!(matches!(
+            storage.read_range("a.partial", 0, 10).await,
+            Err(NodeError::ReservedName(_))
+        ));
+    }
+
+    fn write_aged(path: &std::path::Path, len: usize, age: Duration) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let file = std::fs::File::create(path).unwrap();
+        file.set_len(len as u64).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    #[async_std::test]
+    async fn gc_removes_stale_partials_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let hour = Duration::from_secs(60 * 60);
+        write_aged(&dir.path().join("stale.partial"), 10, 2 * hour);
+        write_aged(&dir.path().join("sub/stale.partial"), 20, 2 * hour);
+        write_aged(&dir.path().join("fresh.partial"), 30, Duration::ZERO);
+        write_aged(&dir.path().join("old-complete"), 40, 2 * hour);
+
+        let mut storage = FsStorage::new(dir.path());
+        storage.open_for_write("open").await.unwrap();
+        write_aged(&dir.path().join("open.partial"), 50, 2 * hour);
+
+        assert_eq!(storage.collect_garbage(hour, None).await.unwrap(), 30);
+        assert!(!dir.path().join("stale.partial").exists());
+        assert!(!dir.path().join("sub/stale.partial").exists());
+        assert!(dir.path().join("fresh.partial").exists());
+        assert!(dir.path().join("open.partial").exists());
+        assert!(dir.path().join("old-complete").exists());
+    }
+
+    #[async_std::test]
+    async fn gc_spares_downloads_opened_during_the_walk() {
+        let dir = tempfile::tempdir().unwrap();
+        let hour = Duration::from_secs(60 * 60);
+        write_aged(&dir.path().join("late.partial"), 10, 2 * hour);
+        write_aged(&dir.path().join("stale.partial"), 20, 2 * hour);
+        let storage = Arc::new(Mutex::new(FsStorage::new(dir.path())));
+
+        let garbage = find_garbage(dir.path().into(), hour, None).await.unwrap();
+        storage.lock().await.open_for_write("late").await.unwrap();
+        // Files that disappeared since the walk, e.g. finalized, are skipped.
+        std::fs::remove_file(dir.path().join("stale.partial")).unwrap();
+
+        assert_eq!(
+            storage.lock().await.remove_garbage(garbage).await.unwrap(),
+            0
+        );
+        assert!(dir.path().join("late.partial").exists());
+    }
+
+    #[async_std::test]
+    async fn gc_enforces_quota_oldest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let minute = Duration::from_secs(60);
+        write_aged(&dir.path().join("oldest"), 10, 3 * minute);
+        write_aged(&dir.path().join("sub/middle"), 10, 2 * minute);
+        write_aged(&dir.path().join("newest"), 10, minute);
+
+        let storage = FsStorage::new(dir.path());
+        assert_eq!(
+            storage
+                .collect_garbage(Duration::MAX, Some(15))
+                .await
+                .unwrap(),
+            20
+        );
+        assert!(!dir.path().join("oldest").exists());
+        assert!(!dir.path().join("sub/middle").exists());
+        assert!(dir.path().join("newest").exists());
+
+        assert_eq!(
+            storage
+                .collect_garbage(Duration::MAX, Some(15))
+                .await
+                .unwrap(),
+            0
+        );
+    }
+}
@@ -0,0 +1,794 @@
+use libp2p::{Multiaddr, PeerId};
+use prost::Message;
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Row};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::chat_log::{message_hash, MessageId};
+use crate::filename::NameMap;
+use crate::wire::ChatEnvelope;
+use crate::NodeError;
+
+/// Schema migrations, applied in order. The database's `user_version` is the
+/// number of migrations already applied.
+const MIGRATIONS: &[&str] = &["
+    CREATE TABLE files (
+        hash BLOB NOT NULL,
+        name TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        provider TEXT NOT NULL,
+        announced_at INTEGER NOT NULL,
+        local_name TEXT,
+        PRIMARY KEY (hash, provider)
+    );
+    CREATE TABLE file_tags (
+        hash BLOB NOT NULL,
+        provider TEXT NOT NULL,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (hash, provider, tag)
+    );
+    CREATE INDEX file_tags_by_tag ON file_tags (tag);
+    CREATE TABLE peers (
+        peer_id TEXT PRIMARY KEY,
+        nickname TEXT,
+        addresses TEXT NOT NULL,
+        last_seen INTEGER NOT NULL
+    );
+    CREATE TABLE transfers (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        hash BLOB NOT NULL,
+        name TEXT NOT NULL,
+        peer_id TEXT NOT NULL,
+        direction TEXT NOT NULL CHECK (direction IN ('upload', 'download')),
+        bytes INTEGER NOT NULL,
+        started_at INTEGER NOT NULL,
+        finished_at INTEGER NOT NULL,
+        succeeded INTEGER NOT NULL
+    );
+    CREATE TABLE chat (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        topic TEXT NOT NULL,
+        author BLOB NOT NULL,
+        seq INTEGER NOT NULL,
+        hash BLOB NOT NULL UNIQUE,
+        envelope BLOB NOT NULL
+    );
+    CREATE INDEX chat_by_topic ON chat (topic, id);
+    CREATE INDEX chat_by_author ON chat (author, seq);
+    CREATE TABLE chat_mentions (
+        peer BLOB NOT NULL,
+        chat_id INTEGER NOT NULL REFERENCES chat (id),
+        PRIMARY KEY (peer, chat_id)
+    );
+"];
+
+/// A file offered by a provider.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileRecord {
+    pub hash: [u8; 32],
+    pub name: String,
+    pub size: u64,
+    pub provider: PeerId,
+    pub announced_at: SystemTime,
+    /// Tags the provider announced the file with, sorted.
+    pub tags: Vec<String>,
+}
+
+/// What we know about a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerRecord {
+    pub peer_id: PeerId,
+    pub nickname: Option<String>,
+    pub addresses: Vec<Multiaddr>,
+    pub last_seen: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+/// A finished or failed file transfer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferRecord {
+    pub hash: [u8; 32],
+    pub name: String,
+    pub peer_id: PeerId,
+    pub direction: Direction,
+    pub bytes: u64,
+    pub started_at: SystemTime,
+    pub finished_at: SystemTime,
+    pub succeeded: bool,
+}
+
+/// Persistent node state backed by SQLite: the file catalog, peer records,
+/// transfer history and chat log.
+///
+/// Timestamps are stored with second precision. Chat messages are stored in
+/// their wire encoding, which their authors' hash chains cover.
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    /// Opens the database at `path`, creating it and applying pending
+    /// migrations as needed.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, NodeError> {
+        Self::migrate(Connection::open(path)?)
+    }
+
+    /// Opens the existing database at `path`, applying pending migrations.
+    /// Unlike [`Store::open`], fails instead of creating a missing database.
+    pub fn open_existing(path: impl AsRef<Path>) -> Result<Self, NodeError> {
+        let path = path.as_ref();
+        let flags = OpenFlags::SQLITE_OPEN_READ_WRITE
+            | OpenFlags::SQLITE_OPEN_URI
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        match Connection::open_with_flags(path, flags) {
+            Ok(conn) => Self::migrate(conn),
+            Err(_) if !path.exists() => Err(NodeError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("database {} does not exist", path.display()),
+            ))),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn open_in_memory() -> Result<Self, NodeError> {
+        Self::migrate(Connection::open_in_memory()?)
+    }
+
+    fn migrate(mut conn: Connection) -> Result<Self, NodeError> {
+        let applied: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        let tx = conn.transaction()?;
+        for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", version + 1)?;
+        }
+        tx.commit()?;
+        Ok(Store { conn })
+    }
+
+    /// Adds `file` to the catalog, replacing an earlier announcement of the
+    /// same content by the same provider but keeping its local name.
+    pub fn upsert_file(&self, file: &FileRecord) -> Result<(), NodeError> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO files (hash, name, size, provider, announced_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (hash, provider) DO UPDATE SET
+                 name = excluded.name,
+                 size = excluded.size,
+                 announced_at = excluded.announced_at",
+            params![
+                file.hash,
+                file.name,
+                file.size,
+                file.provider.to_string(),
+                to_secs(file.announced_at),
+            ],
+        )?;
+        tx.execute(
+            "DELETE FROM file_tags WHERE hash = ?1 AND provider = ?2",
+            params![file.hash, file.provider.to_string()],
+        )?;
+        for tag in &file.tags {
+            tx.execute(
+                "INSERT OR IGNORE INTO file_tags (hash, provider, tag) VALUES (?1, ?2, ?3)",
+                params![file.hash, file.provider.to_string(), tag],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn remove_file(&self, hash: &[u8; 32], provider: &PeerId) -> Result<(), NodeError> {
+        let tx = self.conn.unchecked_transaction()?;
+        for table in ["files", "file_tags"] {
+            tx.execute(
+                &format!("DELETE FROM {table} WHERE hash = ?1 AND provider = ?2"),
+                params![hash, provider.to_string()],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Records that the file `hash` downloaded from `provider` is stored
+    /// under `local_name`, as chosen by a [`NameMap`].
+    pub fn set_local_name(
+        &self,
+        hash: &[u8; 32],
+        provider: &PeerId,
+        local_name: &str,
+    ) -> Result<(), NodeError> {
+        self.conn.execute(
+            "UPDATE files SET local_name = ?3 WHERE hash = ?1 AND provider = ?2",
+            params![hash, provider.to_string(), local_name],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the local names recorded with [`Store::set_local_name`] for
+    /// the files still in the catalog.
+    pub fn name_map(&self) -> Result<NameMap, NodeError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, local_name FROM files WHERE local_name IS NOT NULL")?;
+        let mut map = NameMap::new();
+        for pair in stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })? {
+            let (announced, local) = pair?;
+            map.insert_local(&announced, &local);
+        }
+        Ok(map)
+    }
+
+    /// Returns the catalog, ordered by name.
+    pub fn files(&self) -> Result<Vec<FileRecord>, NodeError> {
+        self.query_files("", [])
+    }
+
+    /// Returns the files tagged with `tag`, ordered by name.
+    pub fn files_tagged(&self, tag: &str) -> Result<Vec<FileRecord>, NodeError> {
+        self.query_files(
+            "WHERE EXISTS (
+                 SELECT 1 FROM file_tags t
+                 WHERE t.hash = f.hash AND t.provider = f.provider AND t.tag = ?1
+             )",
+            params![tag],
+        )
+    }
+
+    fn query_files(
+        &self,
+        filter: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<FileRecord>, NodeError> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT hash, name, size, provider, announced_at, (
+                 SELECT group_concat(tag, char(10)) FROM file_tags t
+                 WHERE t.hash = f.hash AND t.provider = f.provider
+             )
+             FROM files f {filter} ORDER BY name, provider"
+        ))?;
+        let files = stmt
+            .query_map(params, |row| {
+                let tags: Option<String> = row.get(5)?;
+                let mut tags: Vec<_> = tags
+                    .iter()
+                    .flat_map(|t| t.lines())
+                    .map(String::from)
+                    .collect();
+                tags.sort_unstable();
+                Ok(FileRecord {
+                    hash: row.get(0)?,
+                    name: row.get(1)?,
+                    size: row.get(2)?,
+                    provider: parse(row, 3)?,
+                    announced_at: from_secs(row.get(4)?),
+                    tags,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(files)
+    }
+
+    pub fn upsert_peer(&self, peer: &PeerRecord) -> Result<(), NodeError> {
+        let addresses = peer
+            .addresses
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.conn.execute(
+            "INSERT OR REPLACE INTO peers (peer_id, nickname, addresses, last_seen)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                peer.peer_id.to_string(),
+                peer.nickname,
+                addresses,
+                to_secs(peer.last_seen),
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn peer(&self, peer_id: &PeerId) -> Result<Option<PeerRecord>, NodeError> {
+        let peer = self
+            .conn
+            .query_row(
+                "SELECT peer_id, nickname, addresses, last_seen FROM peers WHERE peer_id = ?1",
+                params![peer_id.to_string()],
+                peer_from_row,
+            )
+            .optional()?;
+        Ok(peer)
+    }
+
+    /// Maps the nicknames of known peers to their IDs. If several peers use
+    /// the same nickname, it names the one seen most recently.
+    pub fn nicknames(&self) -> Result<HashMap<String, PeerId>, NodeError> {
+        let mut nicknames = HashMap::new();
+        for peer in self.peers()? {
+            if let Some(nickname) = peer.nickname {
+                nicknames.entry(nickname).or_insert(peer.peer_id);
+            }
+        }
+        Ok(nicknames)
+    }
+
+    /// Returns all known peers, most recently seen first.
+    pub fn peers(&self) -> Result<Vec<PeerRecord>, NodeError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT peer_id, nickname, addresses, last_seen FROM peers ORDER BY last_seen DESC",
+        )?;
+        let peers = stmt
+            .query_map([], peer_from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(peers)
+    }
+
+    pub fn record_transfer(&self, transfer: &TransferRecord) -> Result<(), NodeError> {
+        let direction = match transfer.direction {
+            Direction::Upload => "upload",
+            Direction::Download => "download",
+        };
+        self.conn.execute(
+            "INSERT INTO transfers
+             (hash, name, peer_id, direction, bytes, started_at, finished_at, succeeded)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                transfer.hash,
+                transfer.name,
+                transfer.peer_id.to_string(),
+                direction,
+                transfer.bytes,
+                to_secs(transfer.started_at),
+                to_secs(transfer.finished_at),
+                transfer.succeeded,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the `limit` most recent transfers, newest first.
+    pub fn transfers(&self, limit: usize) -> Result<Vec<TransferRecord>, NodeError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT hash, name, peer_id, direction, bytes, started_at, finished_at, succeeded
+             FROM transfers ORDER BY id DESC LIMIT ?1",
+        )?;
+        let transfers = stmt
+            .query_map(params![limit], |row| {
+                let direction = match row.get_ref(3)?.as_str()? {
+                    "upload" => Direction::Upload,
+                    _ => Direction::Download,
+                };
+                Ok(TransferRecord {
+                    hash: row.get(0)?,
+                    name: row.get(1)?,
+                    peer_id: parse(row, 2)?,
+                    direction,
+                    bytes: row.get(4)?,
+                    started_at: from_secs(row.get(5)?),
+                    finished_at: from_secs(row.get(6)?),
+                    succeeded: row.get(7)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(transfers)
+    }
+
+    /// Adds a message sent or received on its topic. Returns `false` if the
+    /// same message was stored before.
+    pub fn append_chat(&self, message: &ChatEnvelope) -> Result<bool, NodeError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let added = tx.execute(
+            "INSERT OR IGNORE INTO chat (topic, author, seq, hash, envelope)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                message.topic,
+                message.author,
+                message.seq,
+                message_hash(message),
+                message.encode_to_vec(),
+            ],
+        )?;
+        if added > 0 {
+            let id = tx.last_insert_rowid();
+            for peer in &message.mentions {
+                tx.execute(
+                    "INSERT OR IGNORE INTO chat_mentions (peer, chat_id) VALUES (?1, ?2)",
+                    params![peer, id],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(added > 0)
+    }
+
+    /// Returns the last `limit` messages of `topic`, oldest first.
+    pub fn chat_history(&self, topic: &str, limit: usize) -> Result<Vec<ChatEnvelope>, NodeError> {
+        self.query_chat(
+            "SELECT envelope FROM (
+                 SELECT id, envelope FROM chat WHERE topic = ?1 ORDER BY id DESC LIMIT ?2
+             ) ORDER BY id",
+            params![topic, limit],
+        )
+    }
+
+    /// Returns the last `limit` messages mentioning `peer_id` on any topic,
+    /// oldest first.
+    pub fn chat_mentioning(
+        &self,
+        peer_id: &PeerId,
+        limit: usize,
+    ) -> Result<Vec<ChatEnvelope>, NodeError> {
+        self.query_chat(
+            "SELECT envelope FROM (
+                 SELECT id, envelope FROM chat
+                 JOIN chat_mentions ON chat_mentions.chat_id = chat.id
+                 WHERE chat_mentions.peer = ?1 ORDER BY id DESC LIMIT ?2
+             ) ORDER BY id",
+            params![peer_id.to_bytes(), limit],
+        )
+    }
+
+    /// Returns all messages by `author`, ordered by their position in the
+    /// author's hash chain.
+    pub fn chat_by_author(&self, author: &PeerId) -> Result<Vec<ChatEnvelope>, NodeError> {
+        self.query_chat(
+            "SELECT envelope FROM chat WHERE author = ?1 ORDER BY seq, id",
+            params![author.to_bytes()],
+        )
+    }
+
+    /// Returns the message `id`, e.g. the parent of a reply. If the author
+    /// published several versions of it, returns the first one received.
+    pub fn chat_message(&self, id: &MessageId) -> Result<Option<ChatEnvelope>, NodeError> {
+        let mut messages = self.query_chat(
+            "SELECT envelope FROM chat WHERE author = ?1 AND seq = ?2 ORDER BY id LIMIT 1",
+            params![id.author.to_bytes(), id.seq],
+        )?;
+        Ok(messages.pop())
+    }
+
+    /// Returns the message `message` replies to, if it is a reply and the
+    /// parent was received.
+    pub fn chat_parent(&self, message: &ChatEnvelope) -> Result<Option<ChatEnvelope>, NodeError> {
+        match &message.parent_id {
+            Some(parent) => self.chat_message(&MessageId::try_from(parent)?),
+            None => Ok(None),
+        }
+    }
+
+    fn query_chat(
+        &self,
+        sql: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<ChatEnvelope>, NodeError> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let envelopes = stmt
+            .query_map(params, |row| row.get::<_, Vec<u8>>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        envelopes
+            .iter()
+            .map(|bytes| Ok(ChatEnvelope::decode(&bytes[..])?))
+            .collect()
+    }
+}
+
+fn peer_from_row(row: &Row) -> rusqlite::Result<PeerRecord> {
+    let addresses: String = row.get(2)?;
+    let addresses = addresses
+        .lines()
+        .map(|a| Multiaddr::from_str(a).map_err(|e| conversion_error(2, e)))
+        .collect::<Result<_, _>>()?;
+    Ok(PeerRecord {
+        peer_id: parse(row, 0)?,
+        nickname: row.get(1)?,
+        addresses,
+        last_seen: from_secs(row.get(3)?),
+    })
+}
+
+/// Reads column `idx` as a string and parses it, e.g. into a [`PeerId`].
+fn parse<T>(row: &Row, idx: usize) -> rusqlite::Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    row.get_ref(idx)?
+        .as_str()?
+        .parse()
+        .map_err(|e| conversion_error(idx, e))
+}
+
+fn conversion_error(
+    idx: usize,
+    e: impl std::error::Error + Send + Sync + 'static,
+) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, Box::new(e))
+}
+
+fn to_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn from_secs(secs: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn chat(author: PeerId, topic: &str, body: &str, seq: u64) -> ChatEnvelope {
+        ChatEnvelope {
+            author: author.to_bytes(),
+            seq,
+            ..crate::chat::compose(topic, body, seq)
+        }
+    }
+
+    #[test]
+    fn migrations_apply_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("node.db");
+        let peer = PeerId::random();
+
+        let store = Store::open(&path).unwrap();
+        store.append_chat(&chat(peer, "chat", "hi", 0)).unwrap();
+        drop(store);
+
+        let store = Store::open(&path).unwrap();
+        let version: usize = store
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        assert_eq!(store.chat_history("chat", 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn open_existing_does_not_create_databases() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("node.db");
+
+        assert!(matches!(
+            Store::open_existing(&path),
+            Err(NodeError::Io(e)) if e.kind() == io::ErrorKind::NotFound
+        ));
+        assert!(!path.exists());
+
+        Store::open(&path).unwrap();
+        Store::open_existing(&path).unwrap();
+    }
+
+    #[test]
+    fn file_catalog() {
+        let store = Store::open_in_memory().unwrap();
+        let (alice, bob) = (PeerId::random(), PeerId::random());
+        let file = FileRecord {
+            hash: [1; 32],
+            name: "slides.pdf".into(),
+            size: 42,
+            provider: alice,
+            announced_at: at(10),
+            tags: vec!["day1".into(), "slides".into()],
+        };
+        store.upsert_file(&file).unwrap();
+        store
+            .upsert_file(&FileRecord {
+                provider: bob,
+                ..file.clone()
+            })
+            .unwrap();
+        let reannounced = FileRecord {
+            announced_at: at(20),
+            tags: vec!["day2".into(), "slides".into()],
+            ..file.clone()
+        };
+        store.upsert_file(&reannounced).unwrap();
+
+        let files = store.files().unwrap();
+        assert_eq!(files.len(), 2);
+        assert!(files.contains(&reannounced));
+
+        assert_eq!(store.files_tagged("slides").unwrap().len(), 2);
+        assert_eq!(
+            store.files_tagged("day2").unwrap(),
+            vec![reannounced.clone()]
+        );
+        assert!(store.files_tagged("day3").unwrap().is_empty());
+
+        store.remove_file(&[1; 32], &bob).unwrap();
+        assert_eq!(store.files().unwrap(), vec![reannounced]);
+        assert!(store.files_tagged("day1").unwrap().is_empty());
+    }
+
+    #[test]
+    fn local_names_survive_reannouncements() {
+        let store = Store::open_in_memory().unwrap();
+        let provider = PeerId::random();
+        let file = FileRecord {
+            hash: [1; 32],
+            name: "notes.pdf".into(),
+            size: 42,
+            provider,
+            announced_at: at(10),
+            tags: vec![],
+        };
+        store.upsert_file(&file).unwrap();
+        let mut names = NameMap::new();
+        names.insert("Notes.pdf");
+        let local = names.insert(&file.name).to_string();
+        store.set_local_name(&file.hash, &provider, &local).unwrap();
+        store
+            .upsert_file(&FileRecord {
+                announced_at: at(20),
+                ..file.clone()
+            })
+            .unwrap();
+
+        let restored = store.name_map().unwrap();
+        assert_eq!(restored.local("notes.pdf"), Some(local.as_str()));
+        assert_eq!(restored.announced(&local), Some("notes.pdf"));
+
+        store.remove_file(&file.hash, &provider).unwrap();
+        assert_eq!(store.name_map().unwrap().local("notes.pdf"), None);
+    }
+
+    #[test]
+    fn peer_records() {
+        let store = Store::open_in_memory().unwrap();
+        let old = PeerRecord {
+            peer_id: PeerId::random(),
+            nickname: None,
+            addresses: vec![],
+            last_seen: at(1),
+        };
+        let recent = PeerRecord {
+            peer_id: PeerId::random(),
+            nickname: Some("alice".into()),
+            addresses: vec![
+                "/ip4/127.0.0.1/tcp/4001".parse().unwrap(),
+                "/ip6/::1/tcp/4001".parse().unwrap(),
+            ],
+            last_seen: at(2),
+        };
+        store.upsert_peer(&old).unwrap();
+        store.upsert_peer(&recent).unwrap();
+
+        assert_eq!(store.peer(&recent.peer_id).unwrap(), Some(recent.clone()));
+        assert_eq!(store.peer(&PeerId::random()).unwrap(), None);
+        assert_eq!(store.peers().unwrap(), vec![recent, old]);
+    }
+
+    #[test]
+    fn transfer_history_is_newest_first() {
+        let store = Store::open_in_memory().unwrap();
+        let transfer = |name: &str, direction| TransferRecord {
+            hash: [2; 32],
+            name: name.into(),
+            peer_id: PeerId::random(),
+            direction,
+            bytes: 100,
+            started_at: at(1),
+            finished_at: at(2),
+            succeeded: true,
+        };
+        let first = transfer("a", Direction::Download);
+        let second = transfer("b", Direction::Upload);
+        store.record_transfer(&first).unwrap();
+        store.record_transfer(&second).unwrap();
+
+        assert_eq!(store.transfers(10).unwrap(), vec![second.clone(), first]);
+        assert_eq!(store.transfers(1).unwrap(), vec![second]);
+    }
+
+    #[test]
+    fn chat_history_returns_latest_messages_in_order() {
+        let store = Store::open_in_memory().unwrap();
+        let author = PeerId::random();
+        for (i, topic) in ["chat", "other", "chat", "chat"].iter().enumerate() {
+            store
+                .append_chat(&chat(author, topic, &i.to_string(), i as u64))
+                .unwrap();
+        }
+
+        let bodies: Vec<_> = store
+            .chat_history("chat", 2)
+            .unwrap()
+            .into_iter()
+            .map(|m| m.body)
+            .collect();
+        assert_eq!(bodies, ["2", "3"]);
+    }
+
+    #[test]
+    fn finds_the_parent_of_replies() {
+        let store = Store::open_in_memory().unwrap();
+        let alice = PeerId::random();
+        let parent = chat(alice, "chat", "lunch?", 0);
+        let id = MessageId {
+            author: alice,
+            seq: 0,
+        };
+        let reply = crate::chat::reply("chat", id, "yes", 1);
+        let orphan = crate::chat::reply("chat", MessageId { seq: 7, ..id }, "what?", 2);
+        for message in [&parent, &reply, &orphan] {
+            store.append_chat(message).unwrap();
+        }
+
+        assert_eq!(store.chat_message(&id).unwrap(), Some(parent.clone()));
+        assert_eq!(store.chat_parent(&reply).unwrap(), Some(parent.clone()));
+        assert_eq!(store.chat_parent(&orphan).unwrap(), None);
+        assert_eq!(store.chat_parent(&parent).unwrap(), None);
+    }
+
+    #[test]
+    fn finds_mentions() {
+        let store = Store::open_in_memory().unwrap();
+        let (alice, bob) = (PeerId::random(), PeerId::random());
+        for (peer_id, nickname, seen) in [(alice, "alice", 1), (bob, "bob", 3), (alice, "bob", 2)] {
+            store
+                .upsert_peer(&PeerRecord {
+                    peer_id,
+                    nickname: Some(nickname.into()),
+                    addresses: vec![],
+                    last_seen: at(seen),
+                })
+                .unwrap();
+        }
+        let nicknames = store.nicknames().unwrap();
+        assert_eq!(nicknames, HashMap::from([("bob".to_string(), bob)]));
+
+        for (seq, body) in ["@bob hi", "hi all", "@bob @bob again"].iter().enumerate() {
+            let message = chat(alice, "chat", body, seq as u64);
+            assert!(store
+                .append_chat(&crate::chat::mention(message, &nicknames))
+                .unwrap());
+        }
+
+        let bodies = |limit| -> Vec<_> {
+            store
+                .chat_mentioning(&bob, limit)
+                .unwrap()
+                .into_iter()
+                .map(|m| m.body)
+                .collect()
+        };
+        assert_eq!(bodies(10), ["@bob hi", "@bob @bob again"]);
+        assert_eq!(bodies(1), ["@bob @bob again"]);
+        assert!(store.chat_mentioning(&alice, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn chat_by_author_follows_the_chain() {
+        let store = Store::open_in_memory().unwrap();
+        let (alice, bob) = (PeerId::random(), PeerId::random());
+        for (author, topic, seq) in [(alice, "a", 1), (bob, "a", 0), (alice, "b", 0)] {
+            assert!(store.append_chat(&chat(author, topic, "hi", seq)).unwrap());
+        }
+        // Receiving a message again does not store it twice.
+        assert!(!store.append_chat(&chat(alice, "b", "hi", 0)).unwrap());
+
+        assert_eq!(
+            store.chat_by_author(&alice).unwrap(),
+            vec![chat(alice, "b", "hi", 0), chat(alice, "a", "hi", 1)]
+        );
+    }
+}
@@ -0,0 +1,417 @@
+//! Canonical encodings of every wire message, for checking other
+//! implementations against this one.
+//!
+//! The `test-vectors` binary writes each vector to `<name>.bin` and verifies
+//! files produced elsewhere. The golden copies live in `tests/vectors`.
+
+use libp2p::identity::{ed25519, Keypair};
+use libp2p::PeerId;
+use prost::Message;
+use std::collections::HashMap;
+
+use crate::announce;
+use crate::chat;
+use crate::chat_log::{ChatChain, MessageId};
+use crate::document::Document;
+use crate::reaction::react;
+use crate::wire::{
+    AnnouncementBatch, ChatEnvelope, ChunkRequest, DocumentOp, FileAnnouncement, Reaction,
+    SignedAnnouncement,
+};
+use crate::NodeError;
+
+/// A wire message of any type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sample {
+    Announcement(FileAnnouncement),
+    SignedAnnouncement(SignedAnnouncement),
+    AnnouncementBatch(AnnouncementBatch),
+    ChunkRequest(ChunkRequest),
+    Chat(ChatEnvelope),
+    Reaction(Reaction),
+    DocumentOp(DocumentOp),
+}
+
+impl Sample {
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Sample::Announcement(m) => m.encode_to_vec(),
+            Sample::SignedAnnouncement(m) => m.encode_to_vec(),
+            Sample::AnnouncementBatch(m) => m.encode_to_vec(),
+            Sample::ChunkRequest(m) => m.encode_to_vec(),
+            Sample::Chat(m) => m.encode_to_vec(),
+            Sample::Reaction(m) => m.encode_to_vec(),
+            Sample::DocumentOp(m) => m.encode_to_vec(),
+        }
+    }
+
+    /// Decodes `bytes` as a message of the same type as `self`.
+    fn decode_same_type(&self, bytes: &[u8]) -> Result<Sample, NodeError> {
+        Ok(match self {
+            Sample::Announcement(_) => Sample::Announcement(Message::decode(bytes)?),
+            Sample::SignedAnnouncement(_) => Sample::SignedAnnouncement(Message::decode(bytes)?),
+            Sample::AnnouncementBatch(_) => Sample::AnnouncementBatch(Message::decode(bytes)?),
+            Sample::ChunkRequest(_) => Sample::ChunkRequest(Message::decode(bytes)?),
+            Sample::Chat(_) => Sample::Chat(Message::decode(bytes)?),
+            Sample::Reaction(_) => Sample::Reaction(Message::decode(bytes)?),
+            Sample::DocumentOp(_) => Sample::DocumentOp(Message::decode(bytes)?),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vector {
+    pub name: &'static str,
+    pub sample: Sample,
+}
+
+impl Vector {
+    /// Checks that `bytes` is exactly the canonical encoding of this vector.
+    pub fn verify(&self, bytes: &[u8]) -> Result<(), NodeError> {
+        let mismatch = |reason: String| NodeError::VectorMismatch {
+            name: self.name.to_string(),
+            reason,
+        };
+        let decoded = self.sample.decode_same_type(bytes)?;
+        if decoded != self.sample {
+            return Err(mismatch(format!(
+                "expected {:?}, got {decoded:?}",
+                self.sample
+            )));
+        }
+        if bytes != self.sample.encode() {
+            return Err(mismatch(
+                "encoding is not canonical: fields must be in field number order, \
+                 default values omitted and unknown fields absent"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Returns all test vectors.
+pub fn vectors() -> Vec<Vector> {
+    let hash: Vec<u8> = (0..32).collect();
+    let release_hash: [u8; 32] = hash.clone().try_into().expect("32 bytes");
+    let mut chain = ChatChain::new(chat_author());
+    let mut document = Document::new(chat_author());
+    let set = document.set("milk", "2 l");
+    let remove = document.remove("milk");
+    vec![
+        Vector {
+            name: "announcement-empty",
+            sample: Sample::Announcement(FileAnnouncement::default()),
+        },
+        Vector {
+            name: "announcement",
+            sample: Sample::Announcement(FileAnnouncement {
+                name: "slides.pdf".into(),
+                size: 1_048_576,
+                sha256: hash.clone(),
+                tags: vec![],
+                signature: None,
+            }),
+        },
+        Vector {
+            name: "announcement-unicode",
+            sample: Sample::Announcement(FileAnnouncement {
+                name: "caf\u{e9} \u{1f600}.txt".into(),
+                size: u64::MAX,
+                sha256: hash.clone(),
+                tags: vec![],
+                signature: None,
+            }),
+        },
+        Vector {
+            name: "announcement-tagged",
+            sample: Sample::Announcement(FileAnnouncement {
+                name: "notes.pdf".into(),
+                size: 4096,
+                sha256: hash.clone(),
+                tags: vec!["day1".into(), "slides".into()],
+                signature: None,
+            }),
+        },
+        Vector {
+            name: "announcement-release",
+            sample: Sample::Announcement(FileAnnouncement {
+                name: "handout.pdf".into(),
+                size: 4096,
+                sha256: hash.clone(),
+                tags: vec![],
+                signature: Some(
+                    announce::sign_file(&author_keypair(), &release_hash)
+                        .expect("Ed25519 signing cannot fail"),
+                ),
+            }),
+        },
+        Vector {
+            name: "announcement-signed",
+            // Ed25519 signatures are deterministic, so is this vector.
+            sample: Sample::SignedAnnouncement(
+                announce::sign(
+                    &author_keypair(),
+                    FileAnnouncement {
+                        name: "slides.pdf".into(),
+                        size: 1_048_576,
+                        sha256: hash.clone(),
+                        tags: vec![],
+                        signature: None,
+                    },
+                    1_665_086_400,
+                )
+                .expect("Ed25519 signing cannot fail"),
+            ),
+        },
+        Vector {
+            name: "announcement-batch-empty",
+            sample: Sample::AnnouncementBatch(AnnouncementBatch::default()),
+        },
+        Vector {
+            name: "announcement-batch",
+            sample: Sample::AnnouncementBatch(AnnouncementBatch {
+                files: vec![
+                    FileAnnouncement {
+                        name: "slides.pdf".into(),
+                        size: 1_048_576,
+                        sha256: hash.clone(),
+                        tags: vec![],
+                        signature: None,
+                    },
+                    FileAnnouncement {
+                        name: "notes.txt".into(),
+                        size: 0,
+                        sha256: vec![0xff; 32],
+                        tags: vec![],
+                        signature: None,
+                    },
+                ],
+                page: 1,
+                page_count: 3,
+            }),
+        },
+        Vector {
+            name: "chunk-request-empty",
+            sample: Sample::ChunkRequest(ChunkRequest::default()),
+        },
+        Vector {
+            name: "chunk-request",
+            sample: Sample::ChunkRequest(ChunkRequest {
+                sha256: hash,
+                offset: 65_536,
+                length: 65_536,
+            }),
+        },
+        Vector {
+            name: "chat-empty",
+            sample: Sample::Chat(ChatEnvelope::default()),
+        },
+        // The chat vectors below form one chain, in this order.
+        Vector {
+            name: "chat-join",
+            sample: Sample::Chat(chain.link(chat::join("chat", 1_665_000_000))),
+        },
+        Vector {
+            name: "chat",
+            sample: Sample::Chat(chain.link(chat::compose("chat", "Hello, world!", 1_665_000_000))),
+        },
+        Vector {
+            name: "chat-action",
+            sample: Sample::Chat(chain.link(chat::compose("chat", "/me waves", 1_665_000_030))),
+        },
+        Vector {
+            name: "chat-notice",
+            sample: Sample::Chat(chain.link(chat::notice(
+                "chat",
+                "slides.pdf downloaded",
+                1_665_000_060,
+            ))),
+        },
+        Vector {
+            name: "chat-leave",
+            sample: Sample::Chat(chain.link(chat::leave("chat", 1_665_000_090))),
+        },
+        Vector {
+            name: "chat-reply",
+            // A reply to the "chat" vector.
+            sample: Sample::Chat(chain.link(chat::reply(
+                "chat",
+                MessageId {
+                    author: chat_author(),
+                    seq: 1,
+                },
+                "Hi!",
+                1_665_000_120,
+            ))),
+        },
+        Vector {
+            name: "chat-mention",
+            // Mentions the author, nicknamed alice.
+            sample: Sample::Chat(chain.link(chat::mention(
+                chat::compose("chat", "Welcome, @alice!", 1_665_000_150),
+                &HashMap::from([("alice".to_string(), chat_author())]),
+            ))),
+        },
+        Vector {
+            name: "chat-binary",
+            sample: Sample::Chat(chain.link(chat::binary(
+                "chat",
+                vec![0x00, 0x01, 0xfe, 0xff],
+                1_665_000_180,
+            ))),
+        },
+        Vector {
+            name: "reaction-empty",
+            sample: Sample::Reaction(Reaction::default()),
+        },
+        Vector {
+            name: "reaction",
+            // A reaction to the "chat" vector.
+            sample: Sample::Reaction(react(
+                MessageId {
+                    author: chat_author(),
+                    seq: 1,
+                },
+                "\u{1f44d}",
+                chat_author(),
+            )),
+        },
+        Vector {
+            name: "document-op-empty",
+            sample: Sample::DocumentOp(DocumentOp::default()),
+        },
+        Vector {
+            name: "document-set",
+            sample: Sample::DocumentOp(set),
+        },
+        Vector {
+            name: "document-remove",
+            sample: Sample::DocumentOp(remove),
+        },
+    ]
+}
+
+/// Peer ID of the author of the chat, reaction and document vectors and
+/// the provider of the signed announcement, derived from an Ed25519 secret
+/// key of 32 `0x01` bytes.
+pub fn chat_author() -> PeerId {
+    author_keypair().public().to_peer_id()
+}
+
+fn author_keypair() -> Keypair {
+    let secret = ed25519::SecretKey::from_bytes([1; 32]).expect("32 bytes are a valid key");
+    Keypair::Ed25519(secret.into())
+}
+
+pub fn find(name: &str) -> Result<Vector, NodeError> {
+    vectors()
+        .into_iter()
+        .find(|v| v.name == name)
+        .ok_or_else(|| NodeError::UnknownVector(name.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vectors_verify_their_own_encoding() {
+        for vector in vectors() {
+            vector.verify(&vector.sample.encode()).unwrap();
+            assert_eq!(find(vector.name).unwrap(), vector);
+        }
+    }
+
+    #[test]
+    fn names_are_unique() {
+        let mut names: Vec<_> = vectors().iter().map(|v| v.name).collect();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), vectors().len());
+    }
+
+    #[test]
+    fn chat_vectors_form_a_chain() {
+        let messages: Vec<_> = vectors()
+            .into_iter()
+            .filter_map(|v| match v.sample {
+                Sample::Chat(message) if !message.author.is_empty() => Some(message),
+                _ => None,
+            })
+            .collect();
+        let report = crate::chat_log::verify_history(&chat_author(), &messages);
+        assert!(report.is_intact(), "{report:?}");
+        assert_eq!(report.messages, 8);
+    }
+
+    #[test]
+    fn signed_announcement_verifies() {
+        let Sample::SignedAnnouncement(signed) = find("announcement-signed").unwrap().sample else {
+            panic!("not a signed announcement");
+        };
+        let (provider, _) = announce::verify(&signed, 1_665_000_000).unwrap();
+        assert_eq!(provider, chat_author());
+    }
+
+    #[test]
+    fn release_names_its_signer() {
+        let Sample::Announcement(release) = find("announcement-release").unwrap().sample else {
+            panic!("not an announcement");
+        };
+        assert_eq!(announce::signer(&release).unwrap(), Some(chat_author()));
+    }
+
+    #[test]
+    fn rejects_different_message() {
+        let vector = find("chat").unwrap();
+        let other = find("chat-empty").unwrap().sample.encode();
+        assert!(matches!(
+            vector.verify(&other),
+            Err(NodeError::VectorMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_non_canonical_encodings() {
+        let vector = find("chunk-request").unwrap();
+        let canonical = vector.sample.encode();
+
+        // The same fields, offset (2) before sha256 (1).
+        let mut reordered = canonical[34..].to_vec();
+        reordered.extend_from_slice(&canonical[..34]);
+        // An explicit default value, empty `sha256`, in an empty request.
+        let explicit_default = [0x0a, 0x00];
+        // An unknown field 15 set to 1.
+        let mut unknown = canonical.clone();
+        unknown.extend_from_slice(&[0x78, 0x01]);
+
+        for (vector, bytes) in [
+            (&vector, &reordered[..]),
+            (&find("chunk-request-empty").unwrap(), &explicit_default[..]),
+            (&vector, &unknown[..]),
+        ] {
+            assert!(
+                matches!(
+                    vector.verify(bytes),
+                    Err(NodeError::VectorMismatch { ref reason, .. }) if reason.contains("canonical")
+                ),
+                "{bytes:02x?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_bytes() {
+        let vector = find("announcement").unwrap();
+        let encoded = vector.sample.encode();
+        assert!(matches!(
+            vector.verify(&encoded[..encoded.len() - 1]),
+            Err(NodeError::Decode(_))
+        ));
+        assert!(matches!(
+            find("nonexistent"),
+            Err(NodeError::UnknownVector(_))
+        ));
+    }
+}
@@ -0,0 +1,4 @@
+//! Protobuf messages exchanged between nodes, generated from
+//! `proto/wire.proto`.
+
+include!(concat!(env!("OUT_DIR"), "/workshop.wire.rs"));
@@ -0,0 +1,30 @@
+//! Checks the golden files in `tests/vectors` against the test vectors, so
+//! that a change to the wire format cannot go unnoticed.
+//!
+//! Regenerate them with `cargo run --bin test-vectors -- emit tests/vectors`.
+
+use libp2p_workshop_node::test_vectors::vectors;
+use std::path::Path;
+
+#[test]
+fn golden_files_match_vectors() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/vectors");
+    let vectors = vectors();
+
+    for vector in &vectors {
+        let path = dir.join(format!("{}.bin", vector.name));
+        let golden =
+            std::fs::read(&path).unwrap_or_else(|e| panic!("reading {}: {e}", path.display()));
+        vector
+            .verify(&golden)
+            .unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+    }
+
+    let golden_files = std::fs::read_dir(&dir).unwrap().count();
+    assert_eq!(
+        golden_files,
+        vectors.len(),
+        "stale files in {}",
+        dir.display()
+    );
+}